@@ -0,0 +1,176 @@
+// Routes for operators. Requires the subject of the token to be in `admins`.
+use std::{convert::Infallible, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use kube::{
+    api::{ListParams, Patch, PatchParams},
+    Api, Client, ResourceExt,
+};
+use warp::{http::StatusCode, Reply};
+
+use super::{auth::Claims, handlers::Error, json_error_response, json_response, Config};
+use crate::{
+    resource::{is_label_value, CREATED_BY, GROUP_LABEL},
+    Ephemeron,
+};
+
+#[derive(Debug, serde::Deserialize)]
+pub(super) struct ExpiringQuery {
+    /// Include ones expiring within this duration from now. e.g., `1h30m`. Defaults to `0s`.
+    within: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Expiring {
+    id: String,
+    expiration_time: DateTime<Utc>,
+    /// `true` if already expired and waiting to be deleted.
+    expired: bool,
+    created_by: Option<String>,
+    group: Option<String>,
+}
+
+// `GET /admin/expiring?within=1h` -> `[{id, expirationTime, expired, createdBy, group}]`
+// Lists services that are expired or expiring within the duration, sooner first.
+// Nothing is modified.
+#[tracing::instrument(skip(config, client), level = "debug")]
+pub(super) async fn expiring(
+    claims: Claims,
+    query: ExpiringQuery,
+    config: Arc<Config>,
+    client: Client,
+) -> Result<impl Reply, Infallible> {
+    if !config.admins.contains(&claims.sub) {
+        return Ok(Error::Forbidden.into_response());
+    }
+    let within = match query.within.as_deref().map(super::handlers::parse_duration) {
+        None => chrono::Duration::zero(),
+        Some(Some(within)) => within,
+        Some(None) => {
+            return Ok(json_error_response(
+                "Invalid within",
+                StatusCode::BAD_REQUEST,
+            ))
+        }
+    };
+
+    let api: Api<Ephemeron> = Api::all(client);
+    let ephs = match api.list(&ListParams::default()).await {
+        Ok(list) => list.items,
+        Err(err) => return Ok(Error::GetResource(err).into_response()),
+    };
+    let now = Utc::now();
+    let until = now + within;
+    let mut expiring: Vec<Expiring> = ephs
+        .into_iter()
+        .filter(|eph| eph.spec.expiration_time <= until)
+        .map(|eph| Expiring {
+            id: eph.name(),
+            expiration_time: eph.spec.expiration_time,
+            expired: eph.spec.expiration_time <= now,
+            created_by: eph.annotations().get(CREATED_BY).cloned(),
+            group: eph.group().map(ToOwned::to_owned),
+        })
+        .collect();
+    expiring.sort_by_key(|e| e.expiration_time);
+    Ok(json_response(&expiring, StatusCode::OK))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub(super) struct GroupQuery {
+    /// The group to select. `gid.app`
+    group: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PatchResult {
+    id: String,
+    /// `true` if the lifetime was updated.
+    ok: bool,
+    /// The new expiration time if updated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expiration_time: Option<DateTime<Utc>>,
+    /// The reason if it couldn't be updated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+// `PATCH /admin/ephemerons?group={gid}` `{lifetimeMinutes?: u32, duration?: String}` -> `[{id, ok, expirationTime?, message?}]`
+// Sets the lifetime of all the services in the group. Allowed for admins and the members of the group.
+// Responds with the result for each service. Failing to update some doesn't fail the request.
+#[tracing::instrument(skip(config, client), level = "debug")]
+pub(super) async fn patch_group(
+    claims: Claims,
+    query: GroupQuery,
+    payload: super::LifetimePayload,
+    config: Arc<Config>,
+    client: Client,
+) -> Result<impl Reply, Infallible> {
+    let group = query.group;
+    if !config.admins.contains(&claims.sub) && claims.gid.as_ref() != Some(&group) {
+        return Ok(Error::Forbidden.into_response());
+    }
+    if !is_label_value(&group) {
+        return Ok(json_error_response(
+            "Invalid group",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    let duration = match super::handlers::get_lifetime(&payload.lifetime) {
+        Ok(Some(duration)) => duration,
+        Ok(None) => return Ok(Error::MissingLifetime.into_response()),
+        Err(err) => return Ok(err.into_response()),
+    };
+
+    let api: Api<Ephemeron> = Api::all(client);
+    let lp = ListParams::default().labels(&format!("{}={}", GROUP_LABEL, group));
+    let ephs = match api.list(&lp).await {
+        Ok(list) => list.items,
+        Err(err) => return Ok(Error::GetResource(err).into_response()),
+    };
+    let expiration_time = Utc::now() + duration;
+    let mut results = Vec::with_capacity(ephs.len());
+    for eph in ephs {
+        let id = eph.name();
+        if super::handlers::exceeds_max_lifetime(&config, eph.preset(), duration) {
+            results.push(PatchResult {
+                id,
+                ok: false,
+                expiration_time: None,
+                message: Some("lifetime exceeds the maximum".to_owned()),
+            });
+            continue;
+        }
+        // Skip ones modified after listing instead of overwriting.
+        let patch = Patch::Merge(serde_json::json!({
+            "metadata": { "resourceVersion": eph.resource_version() },
+            "spec": { "expirationTime": expiration_time },
+        }));
+        results.push(
+            match api.patch(&id, &PatchParams::default(), &patch).await {
+                Ok(eph) => PatchResult {
+                    id,
+                    ok: true,
+                    expiration_time: Some(eph.spec.expiration_time),
+                    message: None,
+                },
+                Err(err) => {
+                    tracing::debug!("failed to patch {}: {}", id, err);
+                    let message = match err {
+                        kube::Error::Api(err) => err.message,
+                        _ => "failed to update".to_owned(),
+                    };
+                    PatchResult {
+                        id,
+                        ok: false,
+                        expiration_time: None,
+                        message: Some(message),
+                    }
+                }
+            },
+        );
+    }
+    Ok(json_response(&results, StatusCode::OK))
+}
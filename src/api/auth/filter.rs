@@ -2,7 +2,7 @@ use jsonwebtoken as jwt;
 use thiserror::Error;
 use warp::{reject, Filter, Rejection};
 
-use super::{Claims, JWT_SECRET};
+use super::{keys, Claims};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -14,13 +14,29 @@ pub enum Error {
 
     #[error("failed to decode token: {0}")]
     DecodeToken(#[source] jwt::errors::Error),
+
+    #[error("token is restricted")]
+    Restricted,
+
+    #[error("token is revoked")]
+    Revoked,
+
+    #[error("failed to load keys: {0}")]
+    LoadKeys(#[source] keys::KeyError),
 }
 
 impl warp::reject::Reject for Error {}
 
 /// Create a `Filter` that requires a valid `authorization` header, and extracts the claims in JWT.
+/// Rejects the restricted tokens for sharing.
 /// Remember to recover the rejections must be recovered.
 pub fn with_authorization() -> impl Filter<Extract = (Claims,), Error = Rejection> + Clone {
+    with_any_authorization().and_then(unrestricted)
+}
+
+/// Like [`with_authorization`], but also accepts the restricted tokens for sharing.
+/// The handler must check that `share` matches the requested resource.
+pub fn with_any_authorization() -> impl Filter<Extract = (Claims,), Error = Rejection> + Clone {
     warp::header::<String>("authorization")
         .or_else(|_| async { Err(warp::reject::custom(Error::MissingAuthHeader)) })
         .and_then(|auth_header: String| async move {
@@ -39,12 +55,92 @@ pub fn with_authorization() -> impl Filter<Extract = (Claims,), Error = Rejectio
         })
 }
 
+#[derive(Debug, serde::Deserialize)]
+struct TokenQuery {
+    access_token: String,
+}
+
+/// Like [`with_authorization`], but also accepts the token in `access_token` query parameter.
+/// For clients that can't set headers like WebSocket in browsers.
+pub fn with_authorization_or_query() -> impl Filter<Extract = (Claims,), Error = Rejection> + Clone
+{
+    with_authorization()
+        .or(warp::query::<TokenQuery>()
+            .and_then(|query: TokenQuery| async move {
+                decode_jwt(&query.access_token).map_err(reject::custom)
+            })
+            .and_then(unrestricted))
+        .unify()
+}
+
+async fn unrestricted(claims: Claims) -> Result<Claims, Rejection> {
+    if claims.share.is_some() {
+        Err(reject::custom(Error::Restricted))
+    } else {
+        Ok(claims)
+    }
+}
+
 fn decode_jwt(token: &str) -> Result<Claims, Error> {
-    let decoded = jwt::decode::<Claims>(
-        token,
-        &jwt::DecodingKey::from_secret(JWT_SECRET.as_bytes()),
-        &jwt::Validation::default(),
-    )
-    .map_err(Error::DecodeToken)?;
+    let keys = keys::keys().map_err(Error::LoadKeys)?;
+    // Only accept the algorithm of the keys.
+    let mut validation = jwt::Validation::new(keys.algorithm());
+    validation.leeway = super::LEEWAY;
+    let decoded = jwt::decode::<Claims>(token, &keys.decoding_key(), &validation)
+        .map_err(Error::DecodeToken)?;
+    if super::is_revoked(&decoded.claims) {
+        return Err(Error::Revoked);
+    }
     Ok(decoded.claims)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::auth::{create_jwt, encode, timestamp, LEEWAY};
+
+    fn claims(exp: usize) -> Claims {
+        Claims {
+            sub: "user.example".to_owned(),
+            exp,
+            gid: None,
+            share: None,
+            jti: None,
+        }
+    }
+
+    #[test]
+    fn test_create_jwt_ttl() {
+        keys::init_test_keys();
+        let issued = timestamp(chrono::Utc::now());
+        let token = create_jwt(
+            "user.example".to_owned(),
+            Some("group.example".to_owned()),
+            chrono::Duration::seconds(300),
+        )
+        .unwrap();
+        let decoded = decode_jwt(&token).unwrap();
+        assert_eq!(decoded.sub, "user.example");
+        assert_eq!(decoded.gid.as_deref(), Some("group.example"));
+        assert!(decoded.jti.is_some());
+        // `exp` is the TTL after the time it was issued.
+        let ttl = decoded.exp - issued;
+        assert!((300..=301).contains(&ttl), "ttl {}", ttl);
+    }
+
+    #[test]
+    #[allow(clippy::cast_possible_truncation)]
+    fn test_decode_jwt_leeway() {
+        keys::init_test_keys();
+        let now = timestamp(chrono::Utc::now());
+        let leeway = LEEWAY as usize;
+
+        let token = encode(&claims(now + 300)).unwrap();
+        assert!(decode_jwt(&token).is_ok());
+        // Still accepted within the leeway after `exp`.
+        let token = encode(&claims(now - leeway + 5)).unwrap();
+        assert!(decode_jwt(&token).is_ok());
+        let token = encode(&claims(now - leeway - 5)).unwrap();
+        assert!(matches!(decode_jwt(&token), Err(Error::DecodeToken(_))));
+    }
+}
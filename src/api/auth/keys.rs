@@ -0,0 +1,183 @@
+// Keys to sign and verify tokens.
+use jsonwebtoken as jwt;
+use once_cell::sync::OnceCell;
+use thiserror::Error;
+
+static KEYS: OnceCell<JwtKeys> = OnceCell::new();
+
+#[derive(Debug, Error)]
+pub enum KeyError {
+    #[error("JWT_SECRET or JWT_PRIVATE_KEY_PEM and JWT_PUBLIC_KEY_PEM must be set")]
+    Missing,
+
+    #[error("both JWT_PRIVATE_KEY_PEM and JWT_PUBLIC_KEY_PEM must be set to use RS256")]
+    Incomplete,
+
+    #[error("failed to read {0}: {1}")]
+    Read(&'static str, #[source] std::io::Error),
+
+    #[error("invalid key in {0}: {1}")]
+    Invalid(&'static str, #[source] jwt::errors::Error),
+}
+
+pub(super) enum JwtKeys {
+    /// HS256 with the shared secret in `JWT_SECRET`.
+    Hmac(String),
+    /// RS256 with the key pair in the PEM files at `JWT_PRIVATE_KEY_PEM` and `JWT_PUBLIC_KEY_PEM`.
+    /// Others can verify the tokens with only the public key.
+    Rsa {
+        private: jwt::EncodingKey,
+        public: jwt::DecodingKey,
+    },
+}
+
+impl JwtKeys {
+    // RSA if the paths are set. Otherwise, HMAC.
+    fn from_env() -> Result<Self, KeyError> {
+        let private = non_empty_var("JWT_PRIVATE_KEY_PEM");
+        let public = non_empty_var("JWT_PUBLIC_KEY_PEM");
+        match (private, public) {
+            (Some(private), Some(public)) => {
+                let private = read("JWT_PRIVATE_KEY_PEM", &private)?;
+                let public = read("JWT_PUBLIC_KEY_PEM", &public)?;
+                Self::rsa(&private, &public)
+            }
+            (None, None) => non_empty_var("JWT_SECRET")
+                .map(Self::Hmac)
+                .ok_or(KeyError::Missing),
+            _ => Err(KeyError::Incomplete),
+        }
+    }
+
+    fn rsa(private: &[u8], public: &[u8]) -> Result<Self, KeyError> {
+        Ok(Self::Rsa {
+            private: jwt::EncodingKey::from_rsa_pem(private)
+                .map_err(|e| KeyError::Invalid("JWT_PRIVATE_KEY_PEM", e))?,
+            public: jwt::DecodingKey::from_rsa_pem(public)
+                .map_err(|e| KeyError::Invalid("JWT_PUBLIC_KEY_PEM", e))?,
+        })
+    }
+
+    pub(super) fn algorithm(&self) -> jwt::Algorithm {
+        match self {
+            Self::Hmac(_) => jwt::Algorithm::HS256,
+            Self::Rsa { .. } => jwt::Algorithm::RS256,
+        }
+    }
+
+    pub(super) fn encoding_key(&self) -> jwt::EncodingKey {
+        match self {
+            Self::Hmac(secret) => jwt::EncodingKey::from_secret(secret.as_bytes()),
+            Self::Rsa { private, .. } => private.clone(),
+        }
+    }
+
+    pub(super) fn decoding_key(&self) -> jwt::DecodingKey {
+        match self {
+            Self::Hmac(secret) => jwt::DecodingKey::from_secret(secret.as_bytes()),
+            Self::Rsa { public, .. } => public.clone(),
+        }
+    }
+}
+
+/// Load the keys from the environment variables unless loaded.
+/// Call on startup to fail early. Loaded on first use otherwise.
+///
+/// # Errors
+///
+/// Returns [`KeyError`] if the keys are not set or invalid.
+pub fn load_keys() -> Result<(), KeyError> {
+    keys().map(|_| ())
+}
+
+pub(super) fn keys() -> Result<&'static JwtKeys, KeyError> {
+    KEYS.get_or_try_init(JwtKeys::from_env)
+}
+
+fn non_empty_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+fn read(name: &'static str, path: &str) -> Result<Vec<u8>, KeyError> {
+    std::fs::read(path).map_err(|e| KeyError::Read(name, e))
+}
+
+/// Use HS256 with a fixed secret in tests instead of the environment variables.
+#[cfg(test)]
+pub(super) fn init_test_keys() {
+    // Already set by another test.
+    let _ = KEYS.set(JwtKeys::Hmac("test-secret".to_owned()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRIVATE_PEM: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/jwt-private.pem"
+    ));
+    const PUBLIC_PEM: &[u8] = include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/jwt-public.pem"
+    ));
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Claims {
+        sub: String,
+        exp: usize,
+    }
+
+    fn sign(keys: &JwtKeys, claims: &Claims) -> String {
+        jwt::encode(
+            &jwt::Header::new(keys.algorithm()),
+            claims,
+            &keys.encoding_key(),
+        )
+        .unwrap()
+    }
+
+    fn verify(keys: &JwtKeys, token: &str) -> jwt::errors::Result<Claims> {
+        jwt::decode::<Claims>(
+            token,
+            &keys.decoding_key(),
+            &jwt::Validation::new(keys.algorithm()),
+        )
+        .map(|d| d.claims)
+    }
+
+    fn claims() -> Claims {
+        Claims {
+            sub: "user.example".to_owned(),
+            exp: 4_102_444_800,
+        }
+    }
+
+    #[test]
+    fn test_rsa_round_trip() {
+        let keys = JwtKeys::rsa(PRIVATE_PEM, PUBLIC_PEM).unwrap();
+        assert_eq!(keys.algorithm(), jwt::Algorithm::RS256);
+        let token = sign(&keys, &claims());
+        assert_eq!(verify(&keys, &token).unwrap(), claims());
+    }
+
+    #[test]
+    fn test_rsa_rejects_hmac() {
+        let rsa = JwtKeys::rsa(PRIVATE_PEM, PUBLIC_PEM).unwrap();
+        let hmac = JwtKeys::Hmac("secret".to_owned());
+        assert!(verify(&rsa, &sign(&hmac, &claims())).is_err());
+        assert!(verify(&hmac, &sign(&rsa, &claims())).is_err());
+    }
+
+    #[test]
+    fn test_rsa_invalid() {
+        assert!(matches!(
+            JwtKeys::rsa(b"invalid", PUBLIC_PEM),
+            Err(KeyError::Invalid("JWT_PRIVATE_KEY_PEM", _))
+        ));
+        assert!(matches!(
+            JwtKeys::rsa(PRIVATE_PEM, b"invalid"),
+            Err(KeyError::Invalid("JWT_PUBLIC_KEY_PEM", _))
+        ));
+    }
+}
@@ -2,20 +2,27 @@
 use std::{collections::BTreeMap, convert::Infallible, sync::Arc};
 
 use jsonwebtoken as jwt;
-use once_cell::sync::Lazy;
 use thiserror::Error;
 use warp::{http::StatusCode, Reply};
 
 use super::{json_error_response, json_response};
 
 pub mod filter;
+mod keys;
+mod revoked;
+
+pub use keys::{load_keys, KeyError};
 
 // Map of allowed apps and its api key (plain text).
 // Loaded on startup from config file, and passed to token handler.
 pub type Apps = BTreeMap<String, String>;
 
-static JWT_SECRET: Lazy<String> =
-    Lazy::new(|| std::env::var("JWT_SECRET").expect("JWT_SECRET is set"));
+// Label values must be 63 characters or less, and the ids are `{id}.{app}`.
+const MAX_LABEL_LEN: usize = 63;
+/// The longest app name that leaves room for ids of at least one character.
+pub(super) const MAX_APP_LEN: usize = MAX_LABEL_LEN - 2;
+// Seconds a token is still accepted after `exp`. Same as the default of `jsonwebtoken`.
+const LEEWAY: u64 = 60;
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -31,8 +38,17 @@ pub enum Error {
     #[error("invalid gid")]
     InvalidGroupId,
 
+    #[error("app name is too long")]
+    AppNameTooLong,
+
     #[error("failed to create token: {0}")]
     CreateToken(#[source] jwt::errors::Error),
+
+    #[error("failed to load keys: {0}")]
+    LoadKeys(#[source] KeyError),
+
+    #[error("token can't be revoked")]
+    Irrevocable,
 }
 
 impl warp::Reply for Error {
@@ -50,7 +66,19 @@ impl warp::Reply for Error {
                 json_error_response("Invalid gid".to_owned(), StatusCode::BAD_REQUEST)
             }
 
-            Error::CreateToken { .. } => json_error_response(
+            Error::AppNameTooLong => {
+                tracing::error!("app name must be {} characters or less", MAX_APP_LEN);
+                json_error_response(
+                    "Internal Server Error".to_owned(),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                )
+            }
+
+            Error::Irrevocable => {
+                json_error_response("Token can't be revoked".to_owned(), StatusCode::BAD_REQUEST)
+            }
+
+            Error::CreateToken { .. } | Error::LoadKeys { .. } => json_error_response(
                 "Internal Server Error".to_owned(),
                 StatusCode::INTERNAL_SERVER_ERROR,
             ),
@@ -66,6 +94,19 @@ pub struct Claims {
     pub exp: usize,
     /// Optional group id. `gid.app`
     pub gid: Option<String>,
+    /// Restricts the token to read the service with this id. Set for share tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub share: Option<String>,
+    /// Unique id of the token to revoke it. Older tokens don't have it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+}
+
+impl Claims {
+    /// The app of the subject.
+    pub(crate) fn app(&self) -> Option<&str> {
+        self.sub.rsplit_once('.').map(|(_, app)| app)
+    }
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -88,20 +129,26 @@ pub struct TokenResponse {
 // `POST /auth` `{app: String, key: String, uid: String, gid?: String}` -> `{token: String}`
 // Get short-lived token for frontend usage (backend app authenticates on behalf of its user).
 // `uid` must be a string that's unique within `app`.
-// The token's subject is `{uid}.{app}`, and it's valid for 5 minutes.
+// The token's subject is `{uid}.{app}`, and it's valid for `token_ttl_seconds` (5 minutes by default).
 // The api key must be kept secret.
 // Use this token to make requests to create and update resources.
 #[allow(clippy::unused_async)]
-pub async fn token(apps: Arc<Apps>, request: TokenRequest) -> Result<impl Reply, Infallible> {
-    let key = match apps.get(&request.app).ok_or(Error::AppLookup) {
+pub async fn token(
+    config: Arc<super::Config>,
+    request: TokenRequest,
+) -> Result<impl Reply, Infallible> {
+    let key = match config.apps.get(&request.app).ok_or(Error::AppLookup) {
         Err(err) => return Ok(err.into_response()),
         Ok(key) => key,
     };
     if request.key != *key {
         return Ok(Error::InvalidKey.into_response());
     }
-    // Label values must be 63 characters or less.
-    let max_id_len = 63 - (request.app.len() + 1);
+    // Rejected on config load, but don't underflow if it was missed.
+    let max_id_len = match max_id_len(&request.app) {
+        Some(n) => n,
+        None => return Ok(Error::AppNameTooLong.into_response()),
+    };
     if !is_valid_id(&request.uid, max_id_len) {
         return Ok(Error::InvalidUserId.into_response());
     }
@@ -115,7 +162,8 @@ pub async fn token(apps: Arc<Apps>, request: TokenRequest) -> Result<impl Reply,
     } else {
         None
     };
-    let token = match create_jwt(sub, gid) {
+    let ttl = chrono::Duration::seconds(i64::from(config.token_ttl_seconds));
+    let token = match create_jwt(sub, gid, ttl) {
         Err(err) => return Ok(err.into_response()),
         Ok(token) => token,
     };
@@ -123,21 +171,104 @@ pub async fn token(apps: Arc<Apps>, request: TokenRequest) -> Result<impl Reply,
     Ok(json_response(&TokenResponse { token }, StatusCode::OK))
 }
 
+// `GET /auth/me` with `Authorization: Bearer $TOKEN` -> `{sub: String, exp: usize, gid: Option<String>}`
+// Responds with the claims of the token if it's valid. Otherwise, 401.
+// Lets clients confirm the identity and refresh the token before it expires.
+#[allow(clippy::unused_async)]
+pub async fn me(claims: Claims) -> Result<impl Reply, Infallible> {
+    Ok(json_response(&claims, StatusCode::OK))
+}
+
+// `DELETE /auth` with `Authorization: Bearer $TOKEN` -> 204
+// Revoke the token, so it's rejected until it expires.
+#[allow(clippy::unused_async)]
+pub async fn revoke(claims: Claims) -> Result<impl Reply, Infallible> {
+    match &claims.jti {
+        Some(jti) => {
+            revoked::revoke(jti, claims.exp);
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+        None => Ok(Error::Irrevocable.into_response()),
+    }
+}
+
+/// `true` if the token was revoked.
+pub(super) fn is_revoked(claims: &Claims) -> bool {
+    claims.jti.as_deref().map_or(false, revoked::is_revoked)
+}
+
+// The longest id of `{id}.{app}` that fits in a label value. `None` if `app` is too long.
+fn max_id_len(app: &str) -> Option<usize> {
+    (app.len() <= MAX_APP_LEN).then(|| MAX_LABEL_LEN - (app.len() + 1))
+}
+
 fn is_valid_id(s: &str, n: usize) -> bool {
     !s.is_empty() && s.len() <= n && s.chars().all(|c| c.is_ascii_alphanumeric())
 }
 
-fn create_jwt(sub: String, gid: Option<String>) -> Result<String, Error> {
-    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn create_jwt(sub: String, gid: Option<String>, ttl: chrono::Duration) -> Result<String, Error> {
     let exp = chrono::Utc::now()
-        .checked_add_signed(chrono::Duration::minutes(5))
-        .expect("valid timestamp")
-        .timestamp() as usize;
+        .checked_add_signed(ttl)
+        .expect("valid timestamp");
+    encode(&Claims {
+        sub,
+        exp: timestamp(exp),
+        gid,
+        share: None,
+        jti: Some(xid::new().to_string()),
+    })
+}
+
+/// Create a read-only token for the service `id` that expires at `exp`.
+/// The token has the same subject and group as `claims`, so it can only share what the subject can access.
+pub(super) fn create_share_token(
+    claims: &Claims,
+    id: &str,
+    exp: chrono::DateTime<chrono::Utc>,
+) -> Result<String, Error> {
+    encode(&Claims {
+        sub: claims.sub.clone(),
+        exp: timestamp(exp),
+        gid: claims.gid.clone(),
+        share: Some(id.to_owned()),
+        jti: Some(xid::new().to_string()),
+    })
+}
 
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn timestamp(t: chrono::DateTime<chrono::Utc>) -> usize {
+    t.timestamp() as usize
+}
+
+fn encode(claims: &Claims) -> Result<String, Error> {
+    let keys = keys::keys().map_err(Error::LoadKeys)?;
     jwt::encode(
-        &jwt::Header::default(),
-        &Claims { sub, exp, gid },
-        &jwt::EncodingKey::from_secret(JWT_SECRET.as_bytes()),
+        &jwt::Header::new(keys.algorithm()),
+        claims,
+        &keys.encoding_key(),
     )
     .map_err(Error::CreateToken)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_id_len() {
+        assert_eq!(max_id_len("example"), Some(55));
+        // `{id}.{app}` with one character id is 63 characters.
+        assert_eq!(max_id_len(&"x".repeat(61)), Some(1));
+        assert_eq!(max_id_len(&"x".repeat(62)), None);
+        assert_eq!(max_id_len(&"x".repeat(100)), None);
+    }
+
+    #[test]
+    fn test_is_valid_id() {
+        assert!(is_valid_id("user1", 5));
+        assert!(!is_valid_id("user12", 5));
+        assert!(!is_valid_id("", 5));
+        assert!(!is_valid_id("user.1", 10));
+        assert!(!is_valid_id("user", 0));
+    }
+}
@@ -0,0 +1,56 @@
+// Tokens revoked before they expire. Kept in memory until they expire, so each replica has its own.
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    sync::{Mutex, PoisonError},
+};
+
+use once_cell::sync::Lazy;
+
+// `jti` to `exp` of the revoked tokens.
+static REVOKED: Lazy<Mutex<BTreeMap<String, usize>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// Reject the token with `jti` until it expires at `exp`.
+pub(super) fn revoke(jti: &str, exp: usize) {
+    let mut revoked = REVOKED.lock().unwrap_or_else(PoisonError::into_inner);
+    purge(&mut revoked, super::timestamp(chrono::Utc::now()));
+    revoked.insert(jti.to_owned(), exp);
+}
+
+pub(super) fn is_revoked(jti: &str) -> bool {
+    REVOKED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .contains_key(jti)
+}
+
+// Remove the ones rejected anyway. Tokens are still accepted for `LEEWAY` after they expire.
+fn purge(revoked: &mut BTreeMap<String, usize>, now: usize) {
+    let leeway = usize::try_from(super::LEEWAY).unwrap_or(usize::MAX);
+    revoked.retain(|_, &mut exp| exp.saturating_add(leeway) >= now);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_revoke() {
+        assert!(!is_revoked("test-revoke"));
+        revoke("test-revoke", usize::MAX);
+        assert!(is_revoked("test-revoke"));
+    }
+
+    #[test]
+    fn test_purge() {
+        let mut revoked = BTreeMap::new();
+        revoked.insert("a".to_owned(), 1000);
+        purge(&mut revoked, 1000);
+        assert!(revoked.contains_key("a"));
+        // Still accepted within the leeway after `exp`.
+        purge(&mut revoked, 1060);
+        assert!(revoked.contains_key("a"));
+        purge(&mut revoked, 1061);
+        assert!(!revoked.contains_key("a"));
+    }
+}
@@ -0,0 +1,157 @@
+// Interactive sessions in the container over WebSocket.
+use std::{convert::Infallible, sync::Arc, time::Duration};
+
+use futures::{SinkExt, Stream, StreamExt};
+use k8s_openapi::api::core::v1::Pod;
+use kube::{
+    api::{AttachParams, AttachedProcess},
+    Api, Client,
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use warp::{
+    http::StatusCode,
+    ws::{Message, WebSocket, Ws},
+    Reply,
+};
+
+use super::{auth::Claims, handlers::Error, json_error_response, Config};
+use crate::Ephemeron;
+
+// Messages are prefixed with the channel like Kubernetes' streaming protocol.
+const STDIN: u8 = 0;
+const STDOUT: u8 = 1;
+const STDERR: u8 = 2;
+const BUF_SIZE: usize = 4096;
+
+#[derive(Debug, serde::Deserialize)]
+pub(super) struct ExecQuery {
+    /// The command to run. Split by whitespaces.
+    command: String,
+    /// Allocate a TTY. `stderr` is merged into `stdout` when set.
+    #[serde(default)]
+    tty: bool,
+}
+
+// `GET /{id}/exec?command=/bin/sh` upgrades to WebSocket connected to the command in the container.
+// Binary messages start with the channel (0: stdin, 1: stdout, 2: stderr) followed by the data.
+// Closed when the command exits, the client closes, or the service expires.
+#[tracing::instrument(skip(ws, config, client), level = "debug")]
+pub(super) async fn exec(
+    id: String,
+    claims: Claims,
+    query: ExecQuery,
+    ws: Ws,
+    config: Arc<Config>,
+    client: Client,
+) -> Result<impl Reply, Infallible> {
+    let api: Api<Ephemeron> = Api::all(client.clone());
+    let eph = match api.get(&id).await {
+        Ok(eph) => eph,
+        Err(err) => return Ok(Error::GetResource(err).into_response()),
+    };
+    if !super::handlers::has_access(&eph, &claims) {
+        return Ok(Error::Forbidden.into_response());
+    }
+    if !eph.is_pod_ready() {
+        return Ok(json_error_response(
+            "Pod is not ready",
+            StatusCode::CONFLICT,
+        ));
+    }
+    let command: Vec<String> = query
+        .command
+        .split_whitespace()
+        .map(ToOwned::to_owned)
+        .collect();
+    if command.is_empty() {
+        return Ok(json_error_response(
+            "Invalid command",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    let timeout = (eph.spec.expiration_time - chrono::Utc::now())
+        .to_std()
+        .unwrap_or_default();
+
+    let pods: Api<Pod> = Api::namespaced(client, eph.namespace_or(&config.namespace));
+    let tty = query.tty;
+    Ok(ws
+        .on_upgrade(move |socket| async move {
+            let ap = AttachParams::default()
+                .stdin(true)
+                .stdout(true)
+                .stderr(!tty)
+                .tty(tty);
+            match pods.exec(&id, command, &ap).await {
+                Ok(process) => bridge(socket, process, timeout).await,
+                Err(err) => {
+                    tracing::debug!("failed to exec: {}", err);
+                    close(socket, 1011, "failed to exec").await;
+                }
+            }
+        })
+        .into_response())
+}
+
+// Proxy the messages until any of them ends.
+async fn bridge(socket: WebSocket, mut process: AttachedProcess, timeout: Duration) {
+    let (mut tx, mut rx) = socket.split();
+    let mut stdin = process.stdin().expect("stdin is requested");
+    let stdout = process.stdout().map(|r| output(r, STDOUT));
+    let stderr = process.stderr().map(|r| output(r, STDERR));
+    let mut outputs = futures::stream::select(
+        futures::stream::iter(stdout).flatten(),
+        futures::stream::iter(stderr).flatten(),
+    )
+    .boxed();
+
+    let reason = {
+        let input = async {
+            while let Some(Ok(msg)) = rx.next().await {
+                if msg.is_close() {
+                    break;
+                }
+                if let Some((&STDIN, data)) = msg.as_bytes().split_first() {
+                    if stdin.write_all(data).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        };
+        let output = async {
+            while let Some(data) = outputs.next().await {
+                if tx.send(Message::binary(data)).await.is_err() {
+                    break;
+                }
+            }
+        };
+        tokio::select! {
+            () = output => "exited",
+            () = input => "closed",
+            () = tokio::time::sleep(timeout) => "expired",
+        }
+    };
+    let _res = tx.send(Message::close_with(1000_u16, reason)).await;
+}
+
+// Stream of the data read from `reader` prefixed with `channel`.
+fn output<R>(reader: R, channel: u8) -> impl Stream<Item = Vec<u8>>
+where
+    R: AsyncRead + Unpin,
+{
+    futures::stream::unfold(reader, move |mut reader| async move {
+        let mut buf = vec![0; BUF_SIZE + 1];
+        buf[0] = channel;
+        match reader.read(&mut buf[1..]).await {
+            Ok(0) | Err(_) => None,
+            Ok(n) => {
+                buf.truncate(n + 1);
+                Some((buf, reader))
+            }
+        }
+    })
+}
+
+async fn close(mut socket: WebSocket, code: u16, reason: &'static str) {
+    let _res = socket.send(Message::close_with(code, reason)).await;
+}
@@ -1,15 +1,31 @@
-use std::{convert::Infallible, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::Infallible,
+    sync::Arc,
+};
 
 use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::{Pod, ResourceRequirements};
 use kube::{
-    api::{DeleteParams, Patch, PatchParams, PostParams, PropagationPolicy},
+    api::{DeleteParams, ListParams, LogParams, Patch, PatchParams, PostParams, PropagationPolicy},
+    error::ErrorResponse,
     Api, Client, ResourceExt,
 };
 use thiserror::Error;
-use warp::{http::StatusCode, reply, Reply};
+use warp::{
+    http::{header, HeaderValue, StatusCode},
+    hyper::Body,
+    reply, Reply,
+};
 
-use super::{json_error_response, json_response};
-use crate::{Ephemeron, EphemeronSpec};
+use super::{i18n, json_error_response, json_response};
+use crate::{
+    resource::{
+        creator_label, APP_LABEL, CREATED_BY, CREATOR_LABEL, GROUP_LABEL, METADATA_PREFIX,
+        NAMESPACE_ANNOTATION, PRESET_ANNOTATION,
+    },
+    Ephemeron, EphemeronPhase, EphemeronSpec,
+};
 
 #[derive(Debug, Error)]
 pub(super) enum Error {
@@ -17,7 +33,13 @@ pub(super) enum Error {
     PresetLookup(String),
 
     #[error("lifetime {0} is invalid")]
-    InvalidLifetime(u32),
+    InvalidLifetime(String),
+
+    #[error("lifetimeMinutes or duration is required")]
+    MissingLifetime,
+
+    #[error("expiration time {0} is in the past")]
+    PastExpirationTime(DateTime<Utc>),
 
     #[error("failed to create resource: {0}")]
     CreateResource(#[source] kube::Error),
@@ -25,6 +47,9 @@ pub(super) enum Error {
     #[error("failed to update resouce lifetime: {0}")]
     PatchLifetime(#[source] kube::Error),
 
+    #[error("failed to pause or resume resource: {0}")]
+    Pause(#[source] kube::Error),
+
     #[error("failed to get resource: {0}")]
     GetResource(#[source] kube::Error),
 
@@ -33,24 +58,91 @@ pub(super) enum Error {
 
     #[error("forbidden")]
     Forbidden,
+
+    #[error("precondition failed")]
+    PreconditionFailed,
+
+    #[error("{0}")]
+    LimitExceeded(#[source] super::limits::Exceeded),
+
+    #[error("invalid metadata {0}")]
+    InvalidMetadata(String),
+
+    #[error("{0}")]
+    InvalidResources(#[source] super::limits::InvalidResources),
+
+    #[error("{0}")]
+    CreateToken(#[source] super::auth::Error),
+
+    #[error("host {0} is invalid")]
+    InvalidHost(String),
+
+    #[error("callbackUrl {0} is invalid")]
+    InvalidCallbackUrl(String),
+
+    #[error("no valid namespace for app {0}")]
+    InvalidAppNamespace(String),
 }
 
 impl Reply for Error {
     fn into_response(self) -> reply::Response {
         #[allow(clippy::match_same_arms)]
         match self {
-            err @ Error::PresetLookup { .. } => {
-                json_error_response(err.to_string(), StatusCode::NOT_FOUND)
+            Error::PresetLookup(preset) => {
+                i18n::error_response("preset_not_found", Some(preset), StatusCode::NOT_FOUND)
+            }
+            Error::InvalidLifetime(lifetime) => {
+                i18n::error_response("invalid_lifetime", Some(lifetime), StatusCode::BAD_REQUEST)
+            }
+
+            Error::MissingLifetime => json_error_response(
+                "lifetimeMinutes or duration is required",
+                StatusCode::BAD_REQUEST,
+            ),
+
+            Error::PastExpirationTime(time) => json_error_response(
+                format!("expiration time {} is in the past", time.to_rfc3339()),
+                StatusCode::BAD_REQUEST,
+            ),
+
+            Error::Forbidden => i18n::error_response("forbidden", None, StatusCode::FORBIDDEN),
+
+            Error::PreconditionFailed => {
+                json_error_response("Precondition Failed", StatusCode::PRECONDITION_FAILED)
             }
-            err @ Error::InvalidLifetime { .. } => {
+
+            Error::InvalidMetadata(key) => {
+                json_error_response(format!("invalid metadata {}", key), StatusCode::BAD_REQUEST)
+            }
+
+            Error::CreateToken(err) => err.into_response(),
+
+            Error::InvalidHost(host) => {
+                json_error_response(format!("host {} is invalid", host), StatusCode::BAD_REQUEST)
+            }
+
+            Error::InvalidCallbackUrl(url) => json_error_response(
+                format!("callbackUrl {} is invalid", url),
+                StatusCode::BAD_REQUEST,
+            ),
+
+            Error::InvalidAppNamespace(app) => json_error_response(
+                format!("no valid namespace for app {}", app),
+                StatusCode::BAD_REQUEST,
+            ),
+
+            Error::InvalidResources(err) => {
                 json_error_response(err.to_string(), StatusCode::BAD_REQUEST)
             }
 
-            Error::Forbidden => json_error_response("Forbidden", StatusCode::FORBIDDEN),
+            Error::LimitExceeded(err) => {
+                json_error_response(err.to_string(), StatusCode::BAD_REQUEST)
+            }
 
             Error::GetResource(source)
             | Error::CreateResource(source)
-            | Error::PatchLifetime(source) => match source {
+            | Error::PatchLifetime(source)
+            | Error::Pause(source) => match source {
                 kube::Error::Api(err) => {
                     tracing::debug!("Kube Api error: {:?}", err);
                     json_error_response(
@@ -69,13 +161,17 @@ impl Reply for Error {
             },
 
             Error::DeleteResource(source) => match source {
-                kube::Error::Api(err) => StatusCode::from_u16(err.code)
-                    .unwrap_or(StatusCode::BAD_REQUEST)
-                    .into_response(),
+                kube::Error::Api(err) => json_error_response(
+                    err.message,
+                    StatusCode::from_u16(err.code).unwrap_or(StatusCode::BAD_REQUEST),
+                ),
 
                 err => {
                     tracing::warn!("Unexpected Error: {:?}", err);
-                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                    json_error_response(
+                        "Internal Server Error".to_owned(),
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                    )
                 }
             },
         }
@@ -90,11 +186,61 @@ struct Created {
 }
 
 #[derive(serde::Serialize)]
+#[allow(clippy::struct_excessive_bools)]
 #[serde(rename_all = "camelCase")]
 struct HostInfo {
     host: Option<String>,
     expiration_time: DateTime<Utc>,
     tls: bool,
+    /// `true` if reachable from inside the cluster.
+    endpoints_ready: bool,
+    /// `true` if reachable from outside. `None` if not checked because there's no readiness probe.
+    externally_reachable: Option<bool>,
+    /// `true` if the service failed and won't be available.
+    failed: bool,
+    /// `true` if the Pod is stopped until resumed.
+    paused: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    phase: Option<EphemeronPhase>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failure_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failure_message: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct Listed {
+    id: String,
+    #[serde(flatten)]
+    info: HostInfo,
+}
+
+#[derive(serde::Serialize)]
+struct Page {
+    items: Vec<Listed>,
+    /// The token to get the next page. `None` if this is the last page.
+    #[serde(rename = "continue", skip_serializing_if = "Option::is_none")]
+    continue_: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Found<'a> {
+    id: String,
+    expiration_time: DateTime<Utc>,
+    metadata: BTreeMap<&'a str, &'a str>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Shared {
+    token: String,
+    expiration_time: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct Paused {
+    paused: bool,
 }
 
 #[derive(serde::Serialize)]
@@ -103,6 +249,10 @@ struct Expiration {
     expiration_time: DateTime<Utc>,
 }
 
+// `limit` of `GET /`.
+const DEFAULT_LIST_LIMIT: u32 = 100;
+const MAX_LIST_LIMIT: u32 = 500;
+
 // Use this instead of `?` to avoid rejecting.
 macro_rules! warp_try {
     ($expr:expr) => {
@@ -115,38 +265,109 @@ macro_rules! warp_try {
     };
 }
 
-// Annotation used for access control. The claim's `sub` must match to patch.
-const CREATED_BY: &str = "ephemerons.qualified.io/created-by";
-// Label added to pod if the claim contains `gid`.
-const GROUP_LABEL: &str = "ephemerons.qualified.io/group";
-
-#[tracing::instrument(skip(client, presets), level = "debug")]
+#[tracing::instrument(skip(client, config), level = "debug")]
 pub(super) async fn create(
     claims: super::auth::Claims,
     payload: super::PresetPayload,
-    presets: Arc<super::Presets>,
+    config: Arc<super::Config>,
     client: Client,
 ) -> Result<impl Reply, Infallible> {
-    let preset = warp_try!(presets
+    let preset = warp_try!(config
+        .presets
         .get(&payload.preset)
         .ok_or_else(|| Error::PresetLookup(payload.preset.clone())));
 
-    let duration = warp_try!(get_duration(payload.lifetime_minutes));
+    let lifetime = &payload.lifetime;
+    let duration = warp_try!(get_lifetime(lifetime).and_then(|d| d.ok_or(Error::MissingLifetime)));
+    if duration < chrono::Duration::minutes(config.min_lifetime_minutes.into()) {
+        return Ok(Error::InvalidLifetime(lifetime.input()).into_response());
+    }
+    if exceeds_max_lifetime(&config, Some(&payload.preset), duration) {
+        return Ok(Error::InvalidLifetime(lifetime.input()).into_response());
+    }
+    warp_try!(validate_metadata(&payload.metadata));
+    if let Some(url) = &payload.callback_url {
+        if !is_callback_url(url, &config.callback_hosts) {
+            return Ok(Error::InvalidCallbackUrl(url.clone()).into_response());
+        }
+    }
+    let expiration_time = chrono::Utc::now() + duration;
     let id = xid::new().to_string();
+    if let Some(domain) = &config.domain {
+        let host = format!("{}.{}", id, domain);
+        if !crate::resource::is_valid_host(&host) {
+            return Ok(Error::InvalidHost(host).into_response());
+        }
+    }
     let mut eph = Ephemeron::new(
         &id,
         EphemeronSpec {
-            expiration_time: chrono::Utc::now() + duration,
-            service: preset.clone(),
+            expiration_time,
+            service: preset.service.clone(),
+            paused: false,
+            callback_url: payload.callback_url.clone(),
         },
     );
-    eph.annotations_mut()
-        .insert(CREATED_BY.to_owned(), claims.sub);
-    if let Some(gid) = claims.gid {
+    if let Some(gid) = &claims.gid {
+        eph.labels_mut().insert(GROUP_LABEL.to_owned(), gid.clone());
         eph.spec
             .service
             .pod_labels
-            .insert(GROUP_LABEL.to_owned(), gid);
+            .insert(GROUP_LABEL.to_owned(), gid.clone());
+    }
+    if let Some(app) = claims.app() {
+        eph.labels_mut()
+            .insert(APP_LABEL.to_owned(), app.to_owned());
+    }
+    let namespace = if config.namespace_per_app {
+        let app = claims.app().unwrap_or_default();
+        Some(warp_try!(crate::resource::app_namespace(app)
+            .ok_or_else(|| Error::InvalidAppNamespace(app.to_owned()))))
+    } else {
+        None
+    };
+    // Checked before claiming from the pool. The pooled ones have the same spec once claimed.
+    warp_try!(super::limits::validate(&eph.spec, &config.limits).map_err(Error::LimitExceeded));
+
+    let metadata: BTreeMap<String, String> = payload
+        .metadata
+        .into_iter()
+        .map(|(k, v)| (format!("{}{}", METADATA_PREFIX, k), v))
+        .collect();
+    // The pooled ones are running in the controller's namespace.
+    let claimed = if namespace.is_none() {
+        super::pool::claim(
+            client.clone(),
+            &payload.preset,
+            &claims,
+            &metadata,
+            expiration_time,
+            payload.callback_url.as_deref(),
+        )
+        .await
+    } else {
+        None
+    };
+    if let Some(eph) = claimed {
+        return Ok(json_response(
+            &Created {
+                id: eph.name(),
+                expiration_time: eph.spec.expiration_time,
+            },
+            StatusCode::ACCEPTED,
+        ));
+    }
+
+    eph.labels_mut()
+        .insert(CREATOR_LABEL.to_owned(), creator_label(&claims.sub));
+    eph.annotations_mut()
+        .insert(CREATED_BY.to_owned(), claims.sub);
+    eph.annotations_mut()
+        .insert(PRESET_ANNOTATION.to_owned(), payload.preset);
+    eph.annotations_mut().extend(metadata);
+    if let Some(namespace) = namespace {
+        eph.annotations_mut()
+            .insert(NAMESPACE_ANNOTATION.to_owned(), namespace);
     }
 
     let api: Api<Ephemeron> = Api::all(client);
@@ -163,34 +384,115 @@ pub(super) async fn create(
     ))
 }
 
-#[tracing::instrument(skip(client), level = "debug")]
+// `GET /search?meta.{key}={value}` -> `[{id, expirationTime, metadata}]`
+// Lists the services with all the metadata matching.
+// Only the ones created by the caller or its group unless the caller is an admin.
+#[tracing::instrument(skip(config, client), level = "debug")]
+pub(super) async fn search(
+    claims: super::auth::Claims,
+    query: BTreeMap<String, String>,
+    config: Arc<super::Config>,
+    client: Client,
+) -> Result<impl Reply, Infallible> {
+    let wanted: Vec<(&str, &str)> = query
+        .iter()
+        .filter_map(|(k, v)| Some((k.strip_prefix("meta.")?, v.as_str())))
+        .collect();
+    let is_admin = config.admins.contains(&claims.sub);
+    let api: Api<Ephemeron> = Api::all(client);
+    let ephs = warp_try!(api
+        .list(&search_params(&claims, is_admin))
+        .await
+        .map_err(Error::GetResource))
+    .items;
+    let found: Vec<Found> = ephs
+        .iter()
+        .filter(|eph| is_admin || has_access(eph, &claims))
+        .filter_map(|eph| {
+            let metadata = eph.client_metadata();
+            wanted
+                .iter()
+                .all(|(k, v)| metadata.get(k) == Some(v))
+                .then(|| Found {
+                    id: eph.name(),
+                    expiration_time: eph.spec.expiration_time,
+                    metadata,
+                })
+        })
+        .collect();
+    Ok(json_response(&found, StatusCode::OK))
+}
+
+#[tracing::instrument(skip(config, client), level = "debug")]
 pub(super) async fn patch(
     id: String,
     claims: super::auth::Claims,
+    if_match: Option<String>,
     payload: super::PatchPayload,
+    config: Arc<super::Config>,
     client: Client,
 ) -> Result<impl Reply, Infallible> {
     let api: Api<Ephemeron> = Api::all(client);
     let eph = warp_try!(api.get(&id).await.map_err(Error::GetResource));
-    if !has_access(&eph, &claims.sub) {
+    if !has_access(&eph, &claims) {
         return Ok(Error::Forbidden.into_response());
     }
+    // `If-Match: "{resourceVersion}"` for optimistic concurrency.
+    let resource_version = if_match.as_deref().and_then(parse_etag);
+    if resource_version.map_or(false, |v| {
+        Some(v) != eph.metadata.resource_version.as_deref()
+    }) {
+        return Ok(Error::PreconditionFailed.into_response());
+    }
 
-    let duration = warp_try!(get_duration(payload.lifetime_minutes));
-    let patch = Patch::Merge(serde_json::json!({
-        "spec": {
-            "expirationTime": chrono::Utc::now() + duration,
-        },
-    }));
+    let mut patch = serde_json::json!({});
+    let now = chrono::Utc::now();
+    if let Some(expiration_time) = payload.expiration_time {
+        if expiration_time <= now {
+            return Ok(Error::PastExpirationTime(expiration_time).into_response());
+        }
+        // The cap applies to the lifetime from now.
+        if exceeds_max_lifetime(&config, eph.preset(), expiration_time - now) {
+            return Ok(Error::InvalidLifetime(expiration_time.to_rfc3339()).into_response());
+        }
+        patch["spec"]["expirationTime"] = serde_json::json!(expiration_time);
+    } else if let Some(duration) = warp_try!(get_lifetime(&payload.lifetime)) {
+        if exceeds_max_lifetime(&config, eph.preset(), duration) {
+            return Ok(Error::InvalidLifetime(payload.lifetime.input()).into_response());
+        }
+        patch["spec"]["expirationTime"] = serde_json::json!(now + duration);
+    }
+    if let Some(resources) = &payload.resources {
+        patch["spec"]["service"]["resources"] =
+            warp_try!(resize(&config, &claims, &eph, resources));
+    }
+    if patch.as_object().map_or(true, serde_json::Map::is_empty) {
+        return Ok(json_error_response(
+            "Nothing to update",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    // Fails with a conflict if it was modified after we got it.
+    if let Some(v) = resource_version {
+        patch["metadata"] = serde_json::json!({ "resourceVersion": v });
+    }
     let eph = warp_try!(api
-        .patch(&id, &PatchParams::default(), &patch)
+        .patch(&id, &PatchParams::default(), &Patch::Merge(patch))
         .await
-        .map_err(Error::PatchLifetime));
-    Ok(json_response(
-        &Expiration {
-            expiration_time: eph.spec.expiration_time,
-        },
-        StatusCode::OK,
+        .map_err(|err| match err {
+            kube::Error::Api(ErrorResponse { code: 409, .. }) if resource_version.is_some() => {
+                Error::PreconditionFailed
+            }
+            err => Error::PatchLifetime(err),
+        }));
+    Ok(with_etag(
+        json_response(
+            &Expiration {
+                expiration_time: eph.spec.expiration_time,
+            },
+            StatusCode::OK,
+        ),
+        &eph,
     ))
 }
 
@@ -202,20 +504,269 @@ pub(super) async fn get(
 ) -> Result<impl Reply, Infallible> {
     let api: Api<Ephemeron> = Api::all(client);
     let eph = warp_try!(api.get(&id).await.map_err(Error::GetResource));
-    if !has_access(&eph, &claims.sub) {
+    if !can_read(&eph, &claims) {
+        return Ok(Error::Forbidden.into_response());
+    }
+
+    Ok(with_etag(
+        json_response(&host_info(&eph), StatusCode::OK),
+        &eph,
+    ))
+}
+
+fn host_info(eph: &Ephemeron) -> HostInfo {
+    let failure = eph.failure();
+    HostInfo {
+        host: eph.annotations().get("host").cloned(),
+        expiration_time: eph.spec.expiration_time,
+        tls: eph.has_tls(),
+        endpoints_ready: eph.endpoints_ready().unwrap_or(false),
+        externally_reachable: eph.externally_reachable(),
+        failed: failure.is_some(),
+        paused: eph.spec.paused,
+        phase: eph.phase(),
+        failure_reason: failure.and_then(|c| c.reason()).map(ToOwned::to_owned),
+        failure_message: failure.and_then(|c| c.message()).map(ToOwned::to_owned),
+    }
+}
+
+// `GET /?all=true&limit=100&continue={token}` -> `{items: [{id, host, expirationTime, ...}], continue?}`
+// Lists the services created by the caller. With `all=true`, the ones in the caller's group as well.
+// Each page is a page of the resources filtered, so it can have fewer items than `limit` even if
+// there are more. Keep going until `continue` is not set.
+#[tracing::instrument(skip(client), level = "debug")]
+pub(super) async fn list(
+    claims: super::auth::Claims,
+    query: super::ListQuery,
+    client: Client,
+) -> Result<impl Reply, Infallible> {
+    let api: Api<Ephemeron> = Api::all(client);
+    let list = warp_try!(api
+        .list(&list_params(&claims, &query))
+        .await
+        .map_err(Error::GetResource));
+    let items: Vec<Listed> = list
+        .items
+        .iter()
+        .filter(|eph| is_listed(eph, &claims, query.all))
+        .map(|eph| Listed {
+            id: eph.name(),
+            info: host_info(eph),
+        })
+        .collect();
+    let continue_ = list.metadata.continue_.filter(|t| !t.is_empty());
+    Ok(json_response(&Page { items, continue_ }, StatusCode::OK))
+}
+
+// Annotations can't be selected, so select with the labels to page through the matching ones only.
+// Services created by the caller are in the caller's group.
+fn list_selector(claims: &super::auth::Claims, all: bool) -> String {
+    match &claims.gid {
+        Some(gid) if all => format!("{}={}", GROUP_LABEL, gid),
+        _ => format!("{}={}", CREATOR_LABEL, creator_label(&claims.sub)),
+    }
+}
+
+fn list_params(claims: &super::auth::Claims, query: &super::ListQuery) -> ListParams {
+    let mut lp = ListParams::default()
+        .labels(&list_selector(claims, query.all))
+        .limit(
+            query
+                .limit
+                .unwrap_or(DEFAULT_LIST_LIMIT)
+                .clamp(1, MAX_LIST_LIMIT),
+        );
+    if let Some(token) = &query.continue_ {
+        lp = lp.continue_token(token);
+    }
+    lp
+}
+
+// Admins search all of them. Others only the ones in `list` with `all=true`.
+fn search_params(claims: &super::auth::Claims, is_admin: bool) -> ListParams {
+    if is_admin {
+        ListParams::default()
+    } else {
+        ListParams::default().labels(&list_selector(claims, true))
+    }
+}
+
+// The selected ones are checked again because different subjects can have the same label.
+fn is_listed(eph: &Ephemeron, claims: &super::auth::Claims, all: bool) -> bool {
+    is_creator(eph, &claims.sub) || (all && is_group_member(eph, claims))
+}
+
+// `GET /{id}/qr` -> SVG QR code of the URL of the service.
+// Responds with 409 if the host is not available yet.
+#[tracing::instrument(skip(client), level = "debug")]
+pub(super) async fn qr(
+    id: String,
+    claims: super::auth::Claims,
+    client: Client,
+) -> Result<impl Reply, Infallible> {
+    let api: Api<Ephemeron> = Api::all(client);
+    let eph = warp_try!(api.get(&id).await.map_err(Error::GetResource));
+    if !can_read(&eph, &claims) {
+        return Ok(Error::Forbidden.into_response());
+    }
+    let host = match eph.annotations().get("host") {
+        Some(host) => host,
+        None => {
+            return Ok(json_error_response(
+                "Host is not available",
+                StatusCode::CONFLICT,
+            ))
+        }
+    };
+
+    let scheme = if eph.has_tls() { "https" } else { "http" };
+    let url = format!("{}://{}", scheme, host);
+    match qrcode::QrCode::new(url.as_bytes()) {
+        Ok(code) => {
+            let svg = code
+                .render::<qrcode::render::svg::Color>()
+                .min_dimensions(200, 200)
+                .build();
+            Ok(reply::with_header(svg, header::CONTENT_TYPE, "image/svg+xml").into_response())
+        }
+        Err(err) => {
+            tracing::warn!("failed to encode QR code: {}", err);
+            Ok(json_error_response(
+                "Internal Server Error",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+    }
+}
+
+// `POST /{id}/share` -> `{token: String, expirationTime: DateTime<Utc>}`
+// Create a token to share. It can only get the service, and expires with it.
+#[tracing::instrument(skip(client), level = "debug")]
+pub(super) async fn share(
+    id: String,
+    claims: super::auth::Claims,
+    client: Client,
+) -> Result<impl Reply, Infallible> {
+    let api: Api<Ephemeron> = Api::all(client);
+    let eph = warp_try!(api.get(&id).await.map_err(Error::GetResource));
+    if !has_access(&eph, &claims) {
         return Ok(Error::Forbidden.into_response());
     }
 
+    let expiration_time = eph.spec.expiration_time;
+    let token = warp_try!(
+        super::auth::create_share_token(&claims, &id, expiration_time).map_err(Error::CreateToken)
+    );
     Ok(json_response(
-        &HostInfo {
-            host: eph.annotations().get("host").cloned(),
-            expiration_time: eph.spec.expiration_time,
-            tls: eph.spec.service.tls_secret_name.is_some(),
+        &Shared {
+            token,
+            expiration_time,
         },
-        StatusCode::OK,
+        StatusCode::CREATED,
+    ))
+}
+
+// `GET /{id}/logs?tail=100&follow=true` -> `text/plain` logs of the container.
+// With `follow=true`, new lines are streamed until the container exits or the client disconnects.
+// Responds with 404 if the Pod doesn't exist, and 409 if the container hasn't started yet.
+#[tracing::instrument(skip(config, client), level = "debug")]
+pub(super) async fn logs(
+    id: String,
+    claims: super::auth::Claims,
+    query: super::LogsQuery,
+    config: Arc<super::Config>,
+    client: Client,
+) -> Result<impl Reply, Infallible> {
+    let api: Api<Ephemeron> = Api::all(client.clone());
+    let eph = warp_try!(api.get(&id).await.map_err(Error::GetResource));
+    if !has_access(&eph, &claims) {
+        return Ok(Error::Forbidden.into_response());
+    }
+
+    let pods: Api<Pod> = Api::namespaced(client, eph.namespace_or(&config.namespace));
+    let lp = LogParams {
+        container: Some("container".to_owned()),
+        follow: query.follow,
+        tail_lines: query.tail.map(i64::from),
+        ..LogParams::default()
+    };
+    match pods.log_stream(&id, &lp).await {
+        Ok(stream) => Ok(reply::with_header(
+            reply::Response::new(Body::wrap_stream(stream)),
+            header::CONTENT_TYPE,
+            "text/plain; charset=utf-8",
+        )
+        .into_response()),
+        Err(kube::Error::Api(ErrorResponse { code: 404, .. })) => {
+            Ok(json_error_response("Pod not found", StatusCode::NOT_FOUND))
+        }
+        // The container is still waiting to start.
+        Err(kube::Error::Api(ErrorResponse { code: 400, .. })) => Ok(json_error_response(
+            "Pod is not ready",
+            StatusCode::CONFLICT,
+        )),
+        Err(err) => Ok(Error::GetResource(err).into_response()),
+    }
+}
+
+// `POST /{id}/pause` -> `{paused: true}`
+// Stop the service by deleting the Pod. The `Service`, the `Ingress`, and the host are kept.
+#[tracing::instrument(skip(client), level = "debug")]
+pub(super) async fn pause(
+    id: String,
+    claims: super::auth::Claims,
+    client: Client,
+) -> Result<impl Reply, Infallible> {
+    set_paused(&id, &claims, true, client).await
+}
+
+// `POST /{id}/resume` -> `{paused: false}`
+// Start the service again by recreating the Pod.
+#[tracing::instrument(skip(client), level = "debug")]
+pub(super) async fn resume(
+    id: String,
+    claims: super::auth::Claims,
+    client: Client,
+) -> Result<impl Reply, Infallible> {
+    set_paused(&id, &claims, false, client).await
+}
+
+async fn set_paused(
+    id: &str,
+    claims: &super::auth::Claims,
+    paused: bool,
+    client: Client,
+) -> Result<reply::Response, Infallible> {
+    let api: Api<Ephemeron> = Api::all(client);
+    let eph = warp_try!(api.get(id).await.map_err(Error::GetResource));
+    if !has_access(&eph, claims) {
+        return Ok(Error::Forbidden.into_response());
+    }
+
+    let eph = warp_try!(api
+        .patch(
+            id,
+            &PatchParams::default(),
+            &Patch::Merge(paused_patch(paused))
+        )
+        .await
+        .map_err(Error::Pause));
+    Ok(with_etag(
+        json_response(
+            &Paused {
+                paused: eph.spec.paused,
+            },
+            StatusCode::OK,
+        ),
+        &eph,
     ))
 }
 
+// The controller deletes the Pod while paused, and recreates it when resumed.
+fn paused_patch(paused: bool) -> serde_json::Value {
+    serde_json::json!({ "spec": { "paused": paused } })
+}
+
 #[tracing::instrument(skip(client), level = "debug")]
 pub(super) async fn delete(
     id: String,
@@ -224,25 +775,614 @@ pub(super) async fn delete(
 ) -> Result<impl Reply, Infallible> {
     let api: Api<Ephemeron> = Api::all(client);
     let eph = warp_try!(api.get(&id).await.map_err(Error::GetResource));
-    if !has_access(&eph, &claims.sub) {
+    if !has_access(&eph, &claims) {
         return Ok(Error::Forbidden.into_response());
     }
 
+    // Same as the controller. See `expiry::reconcile`.
     let dp = DeleteParams {
-        propagation_policy: Some(PropagationPolicy::Background),
+        propagation_policy: Some(PropagationPolicy::Foreground),
         ..DeleteParams::default()
     };
     let _res = warp_try!(api.delete(&id, &dp).await.map_err(Error::DeleteResource));
     Ok(StatusCode::NO_CONTENT.into_response())
 }
 
-fn get_duration(minutes: u32) -> Result<chrono::Duration, Error> {
-    let duration = std::time::Duration::from_secs((minutes * 60).into());
-    chrono::Duration::from_std(duration).map_err(|_| Error::InvalidLifetime(minutes))
+// Only the apps in `resize_apps` can change the resources, and only within the limits.
+fn resize(
+    config: &super::Config,
+    claims: &super::auth::Claims,
+    eph: &Ephemeron,
+    resources: &ResourceRequirements,
+) -> Result<serde_json::Value, Error> {
+    if !claims
+        .app()
+        .map_or(false, |app| config.resize_apps.contains(app))
+    {
+        return Err(Error::Forbidden);
+    }
+    super::limits::validate_resources(resources, &config.limits.max_resources)
+        .map_err(Error::InvalidResources)?;
+    Ok(resources_patch(
+        eph.spec.service.resources.as_ref(),
+        resources,
+    ))
+}
+
+// Merge patch to replace `current` with `new`. Removed quantities are set to `null`.
+fn resources_patch(
+    current: Option<&ResourceRequirements>,
+    new: &ResourceRequirements,
+) -> serde_json::Value {
+    let mut patch = serde_json::json!(new);
+    if let Some(current) = current {
+        for (kind, quantities) in [("limits", &current.limits), ("requests", &current.requests)] {
+            for name in quantities.iter().flat_map(BTreeMap::keys) {
+                if patch[kind].get(name).is_none() {
+                    patch[kind][name] = serde_json::Value::Null;
+                }
+            }
+        }
+    }
+    patch
+}
+
+/// The requested lifetime. `None` if neither field is set. Must be positive.
+// Zero would expire immediately, and be deleted as soon as it's created.
+pub(super) fn get_lifetime(lifetime: &super::Lifetime) -> Result<Option<chrono::Duration>, Error> {
+    let duration = match (&lifetime.duration, lifetime.lifetime_minutes) {
+        (Some(duration), _) => parse_duration(duration),
+        (None, Some(minutes)) => {
+            let duration = std::time::Duration::from_secs(u64::from(minutes) * 60);
+            chrono::Duration::from_std(duration).ok()
+        }
+        (None, None) => return Ok(None),
+    };
+    duration
+        .filter(|d| *d > chrono::Duration::zero())
+        .map(Some)
+        .ok_or_else(|| Error::InvalidLifetime(lifetime.input()))
+}
+
+/// Parse a duration like `90s`, `30m`, `2h`, `1d`, or `1h30m`.
+pub(super) fn parse_duration(s: &str) -> Option<chrono::Duration> {
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    let mut seconds: u64 = 0;
+    while !rest.is_empty() {
+        let end = rest.find(|c: char| !c.is_ascii_digit())?;
+        let n: u64 = rest[..end].parse().ok()?;
+        let mut units = rest[end..].chars();
+        let unit = match units.next()? {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return None,
+        };
+        seconds = seconds.checked_add(n.checked_mul(unit)?)?;
+        rest = units.as_str();
+    }
+    chrono::Duration::from_std(std::time::Duration::from_secs(seconds)).ok()
+}
+
+// Lifetimes over the maximum for the preset would keep the service around for too long.
+pub(super) fn exceeds_max_lifetime(
+    config: &super::Config,
+    preset: Option<&str>,
+    duration: chrono::Duration,
+) -> bool {
+    config.max_lifetime_minutes(preset).map_or(false, |max| {
+        duration > chrono::Duration::minutes(max.into())
+    })
+}
+
+// Set `ETag` header to the `resourceVersion`.
+fn with_etag(mut res: reply::Response, eph: &Ephemeron) -> reply::Response {
+    if let Some(value) = eph
+        .metadata
+        .resource_version
+        .as_ref()
+        .and_then(|v| HeaderValue::from_str(&format!("\"{}\"", v)).ok())
+    {
+        res.headers_mut().insert(header::ETAG, value);
+    }
+    res
+}
+
+// Get the `resourceVersion` from `If-Match` header value. `*` matches any.
+fn parse_etag(value: &str) -> Option<&str> {
+    let value = value.trim();
+    if value == "*" {
+        return None;
+    }
+    let value = value.strip_prefix("W/").unwrap_or(value);
+    Some(value.trim_matches('"'))
+}
+
+// Metadata keys are used as the name of the annotations, and values are limited in length.
+const MAX_METADATA: usize = 16;
+const MAX_METADATA_VALUE_LEN: usize = 256;
+
+fn validate_metadata(metadata: &BTreeMap<String, String>) -> Result<(), Error> {
+    if metadata.len() > MAX_METADATA {
+        return Err(Error::LimitExceeded(super::limits::Exceeded {
+            field: "metadata",
+            max: MAX_METADATA,
+        }));
+    }
+    for (key, value) in metadata {
+        if !crate::resource::is_label_value(key) || value.len() > MAX_METADATA_VALUE_LEN {
+            return Err(Error::InvalidMetadata(key.clone()));
+        }
+    }
+    Ok(())
+}
+
+// Only absolute `http` or `https` URLs to the allowed hosts can be called back.
+// The controller sends them from inside the cluster, so any other host could reach the internal services.
+fn is_callback_url(url: &str, hosts: &BTreeSet<String>) -> bool {
+    url.parse::<warp::http::Uri>().map_or(false, |uri| {
+        matches!(uri.scheme_str(), Some("http" | "https"))
+            && uri
+                .host()
+                .map_or(false, |host| hosts.contains(&host.to_ascii_lowercase()))
+    })
+}
+
+// Share tokens can only read the shared one.
+fn can_read(eph: &Ephemeron, claims: &super::auth::Claims) -> bool {
+    has_access(eph, claims)
+        && claims
+            .share
+            .as_ref()
+            .map_or(true, |id| Some(id) == eph.metadata.name.as_ref())
+}
+
+// The creator and the members of the group have access.
+pub(super) fn has_access(eph: &Ephemeron, claims: &super::auth::Claims) -> bool {
+    is_creator(eph, &claims.sub) || is_group_member(eph, claims)
 }
 
-fn has_access(eph: &Ephemeron, sub: &str) -> bool {
+fn is_creator(eph: &Ephemeron, sub: &str) -> bool {
     eph.annotations()
         .get(CREATED_BY)
         .map_or(false, |by| by == sub)
 }
+
+fn is_group_member(eph: &Ephemeron, claims: &super::auth::Claims) -> bool {
+    claims.gid.is_some() && eph.group() == claims.gid.as_deref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lifetime(duration: Option<&str>, lifetime_minutes: Option<u32>) -> super::super::Lifetime {
+        super::super::Lifetime {
+            lifetime_minutes,
+            duration: duration.map(ToOwned::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_get_lifetime() {
+        let minutes = |l| get_lifetime(&l).map(|d| d.map(|d| d.num_minutes()));
+        assert!(matches!(minutes(lifetime(None, None)), Ok(None)));
+        assert!(matches!(minutes(lifetime(None, Some(1))), Ok(Some(1))));
+        assert!(matches!(minutes(lifetime(Some("2h"), None)), Ok(Some(120))));
+        // `duration` takes precedence.
+        assert!(matches!(
+            minutes(lifetime(Some("2h"), Some(1))),
+            Ok(Some(120))
+        ));
+        assert!(matches!(
+            minutes(lifetime(None, Some(0))),
+            Err(Error::InvalidLifetime(_))
+        ));
+        assert!(matches!(
+            minutes(lifetime(Some("0m"), None)),
+            Err(Error::InvalidLifetime(_))
+        ));
+        assert!(matches!(
+            minutes(lifetime(Some("soon"), None)),
+            Err(Error::InvalidLifetime(_))
+        ));
+    }
+
+    #[test]
+    fn test_exceeds_max_lifetime() {
+        let minutes = chrono::Duration::minutes;
+        let unlimited = super::super::test_config("{}");
+        assert!(!exceeds_max_lifetime(
+            &unlimited,
+            Some("nginx"),
+            minutes(100_000)
+        ));
+
+        let config = super::super::test_config(
+            "max_lifetime_minutes: 60\npresets: {nginx: {image: nginx, port: 80}, long: {image: nginx, port: 80, maxLifetimeMinutes: 120}}",
+        );
+        assert!(!exceeds_max_lifetime(&config, Some("nginx"), minutes(60)));
+        assert!(exceeds_max_lifetime(&config, Some("nginx"), minutes(61)));
+        assert!(exceeds_max_lifetime(&config, None, minutes(61)));
+        // The preset's own maximum.
+        assert!(!exceeds_max_lifetime(&config, Some("long"), minutes(120)));
+        assert!(exceeds_max_lifetime(&config, Some("long"), minutes(121)));
+    }
+
+    #[test]
+    fn test_is_callback_url() {
+        let hosts = BTreeSet::from(["hooks.example.com".to_owned()]);
+        assert!(is_callback_url("https://hooks.example.com/ready", &hosts));
+        assert!(is_callback_url("http://HOOKS.example.com:8080/", &hosts));
+        assert!(!is_callback_url("ftp://hooks.example.com/", &hosts));
+        assert!(!is_callback_url("/ready", &hosts));
+        assert!(!is_callback_url(
+            "https://hooks.example.com.evil.com/",
+            &hosts
+        ));
+        assert!(!is_callback_url("http://169.254.169.254/latest", &hosts));
+        assert!(!is_callback_url(
+            "https://hooks.example.com/",
+            &BTreeSet::new()
+        ));
+    }
+
+    #[test]
+    fn test_paused_patch() {
+        assert_eq!(
+            paused_patch(true),
+            serde_json::json!({ "spec": { "paused": true } })
+        );
+        // Only `paused` is changed when resumed.
+        assert_eq!(
+            paused_patch(false),
+            serde_json::json!({ "spec": { "paused": false } })
+        );
+    }
+
+    fn claims(sub: &str, gid: Option<&str>) -> super::super::auth::Claims {
+        super::super::auth::Claims {
+            sub: sub.to_owned(),
+            exp: 0,
+            gid: gid.map(ToOwned::to_owned),
+            share: None,
+            jti: None,
+        }
+    }
+
+    // Created by `sub` in the group `gid`, like `create`.
+    fn created(sub: &str, gid: Option<&str>) -> Ephemeron {
+        let mut eph =
+            crate::resource::test_ephemeron(serde_json::json!({"image": "nginx", "port": 80}));
+        eph.annotations_mut()
+            .insert(CREATED_BY.to_owned(), sub.to_owned());
+        eph.labels_mut()
+            .insert(CREATOR_LABEL.to_owned(), creator_label(sub));
+        if let Some(gid) = gid {
+            eph.labels_mut()
+                .insert(GROUP_LABEL.to_owned(), gid.to_owned());
+        }
+        eph
+    }
+
+    #[test]
+    fn test_list_selector() {
+        let user = claims("user.example", None);
+        let creator = format!("{}={}", CREATOR_LABEL, creator_label("user.example"));
+        assert_eq!(list_selector(&user, false), creator);
+        // Only the creator without a group.
+        assert_eq!(list_selector(&user, true), creator);
+
+        let member = claims("user.example", Some("team.example"));
+        assert_eq!(list_selector(&member, false), creator);
+        assert_eq!(
+            list_selector(&member, true),
+            format!("{}=team.example", GROUP_LABEL)
+        );
+    }
+
+    #[test]
+    fn test_is_listed() {
+        let member = claims("user.example", Some("team.example"));
+        let own = created("user.example", Some("team.example"));
+        let other_member = created("other.example", Some("team.example"));
+        let other_group = created("other.example", Some("other-team.example"));
+        assert!(is_listed(&own, &member, false));
+        assert!(!is_listed(&other_member, &member, false));
+        assert!(is_listed(&other_member, &member, true));
+        assert!(!is_listed(&other_group, &member, true));
+        // Not listed with the same label but a different creator.
+        let mut same_label = created("other.example", None);
+        same_label
+            .labels_mut()
+            .insert(CREATOR_LABEL.to_owned(), creator_label("user.example"));
+        assert!(!is_listed(&same_label, &claims("user.example", None), true));
+    }
+
+    #[test]
+    fn test_list_params() {
+        let query = |q: serde_json::Value| -> super::super::ListQuery {
+            serde_json::from_value(q).unwrap()
+        };
+        let user = claims("user.example", Some("team.example"));
+
+        let lp = list_params(&user, &query(serde_json::json!({})));
+        assert_eq!(lp.limit, Some(DEFAULT_LIST_LIMIT));
+        assert_eq!(lp.continue_token, None);
+        assert_eq!(lp.label_selector, Some(list_selector(&user, false)));
+
+        let lp = list_params(
+            &user,
+            &query(serde_json::json!({"all": true, "limit": 10, "continue": "next"})),
+        );
+        assert_eq!(lp.limit, Some(10));
+        assert_eq!(lp.continue_token.as_deref(), Some("next"));
+        assert_eq!(lp.label_selector, Some(list_selector(&user, true)));
+
+        // Clamped to at least one, and at most the maximum.
+        let limit = |n: u32| list_params(&user, &query(serde_json::json!({ "limit": n }))).limit;
+        assert_eq!(limit(0), Some(1));
+        assert_eq!(limit(MAX_LIST_LIMIT), Some(MAX_LIST_LIMIT));
+        assert_eq!(limit(MAX_LIST_LIMIT + 1), Some(MAX_LIST_LIMIT));
+    }
+
+    #[test]
+    fn test_page() {
+        let eph = created("user.example", None);
+        let page = |continue_: Option<&str>| {
+            serde_json::to_value(&Page {
+                items: vec![Listed {
+                    id: eph.name(),
+                    info: host_info(&eph),
+                }],
+                continue_: continue_.map(ToOwned::to_owned),
+            })
+            .unwrap()
+        };
+        let last = page(None);
+        assert_eq!(last["items"][0]["id"], "test");
+        assert_eq!(last["items"][0]["expirationTime"], "2030-01-01T00:00:00Z");
+        assert!(last.get("continue").is_none());
+        assert_eq!(page(Some("next"))["continue"], "next");
+    }
+
+    #[test]
+    fn test_has_access() {
+        let eph = created("user.example", Some("team.example"));
+        // The creator with or without the group.
+        assert!(has_access(&eph, &claims("user.example", None)));
+        assert!(has_access(
+            &eph,
+            &claims("user.example", Some("team.example"))
+        ));
+        // The members of the group.
+        assert!(has_access(
+            &eph,
+            &claims("other.example", Some("team.example"))
+        ));
+        assert!(is_group_member(
+            &eph,
+            &claims("other.example", Some("team.example"))
+        ));
+        // Denied across the groups and without a group.
+        assert!(!has_access(
+            &eph,
+            &claims("other.example", Some("other-team.example"))
+        ));
+        assert!(!has_access(&eph, &claims("other.example", None)));
+        // Resources without a group aren't shared with the callers without a group.
+        let ungrouped = created("user.example", None);
+        assert!(!is_group_member(&ungrouped, &claims("other.example", None)));
+        assert!(!has_access(
+            &ungrouped,
+            &claims("other.example", Some("team.example"))
+        ));
+        // Being an admin doesn't grant access. See `search` and the admin routes.
+        assert!(!has_access(&eph, &claims("admin.example", None)));
+    }
+
+    #[test]
+    fn test_can_read() {
+        let eph = created("user.example", Some("team.example"));
+        assert!(can_read(&eph, &claims("user.example", None)));
+        assert!(can_read(
+            &eph,
+            &claims("other.example", Some("team.example"))
+        ));
+
+        let share = |sub: &str, gid: Option<&str>, id: &str| super::super::auth::Claims {
+            share: Some(id.to_owned()),
+            ..claims(sub, gid)
+        };
+        // Share tokens can only read the shared one.
+        assert!(can_read(&eph, &share("user.example", None, "test")));
+        assert!(!can_read(&eph, &share("user.example", None, "other")));
+        assert!(can_read(
+            &eph,
+            &share("other.example", Some("team.example"), "test")
+        ));
+        // And only if the subject still has access.
+        assert!(!can_read(&eph, &share("other.example", None, "test")));
+    }
+
+    #[allow(clippy::needless_pass_by_value)]
+    fn resources(value: serde_json::Value) -> ResourceRequirements {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_resources_patch() {
+        let new = resources(serde_json::json!({"limits": {"memory": "1Gi"}}));
+        assert_eq!(
+            resources_patch(None, &new),
+            serde_json::json!({"limits": {"memory": "1Gi"}})
+        );
+        // Removes the ones not in the new, and replaces the rest.
+        let current = resources(serde_json::json!({
+            "limits": {"cpu": "1", "memory": "512Mi"},
+            "requests": {"cpu": "500m"},
+        }));
+        assert_eq!(
+            resources_patch(Some(&current), &new),
+            serde_json::json!({
+                "limits": {"cpu": null, "memory": "1Gi"},
+                "requests": {"cpu": null},
+            })
+        );
+    }
+
+    #[test]
+    fn test_resize() {
+        let config = super::super::test_config(
+            "resize_apps: [example]\nlimits: {max_resources: {cpu: '1', memory: 1Gi}}",
+        );
+        let mut eph = created("user.example", None);
+        eph.spec.service.resources = Some(resources(serde_json::json!({
+            "limits": {"cpu": "500m", "memory": "256Mi"},
+        })));
+        let user = claims("user.example", None);
+
+        let patch = resize(
+            &config,
+            &user,
+            &eph,
+            &resources(serde_json::json!({"limits": {"memory": "1Gi"}})),
+        )
+        .unwrap();
+        assert_eq!(
+            patch,
+            serde_json::json!({"limits": {"cpu": null, "memory": "1Gi"}})
+        );
+
+        let status = |claims: &super::super::auth::Claims, value: serde_json::Value| {
+            resize(&config, claims, &eph, &resources(value))
+                .unwrap_err()
+                .into_response()
+                .status()
+        };
+        // Outside `resize_apps`.
+        assert_eq!(
+            status(
+                &claims("user.other", None),
+                serde_json::json!({"limits": {"memory": "1Gi"}})
+            ),
+            StatusCode::FORBIDDEN
+        );
+        // Over or not in `limits.max_resources`.
+        assert_eq!(
+            status(&user, serde_json::json!({"limits": {"memory": "2Gi"}})),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(&user, serde_json::json!({"requests": {"cpu": "2"}})),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(
+                &user,
+                serde_json::json!({"limits": {"ephemeral-storage": "1Gi"}})
+            ),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_lifetime() {
+        let err = get_lifetime(&lifetime(None, None))
+            .and_then(|d| d.ok_or(Error::MissingLifetime))
+            .unwrap_err();
+        let res = err.into_response();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let message: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            message["message"],
+            "lifetimeMinutes or duration is required"
+        );
+    }
+
+    #[test]
+    fn test_validate_metadata() {
+        let metadata = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+                .collect()
+        };
+        assert!(validate_metadata(&metadata(&[])).is_ok());
+        assert!(validate_metadata(&metadata(&[("course", "rust-101"), ("user_id", "")])).is_ok());
+        let max_value = "x".repeat(MAX_METADATA_VALUE_LEN);
+        assert!(validate_metadata(&metadata(&[("k", &max_value)])).is_ok());
+
+        let long_key = "k".repeat(64);
+        let long_value = "x".repeat(MAX_METADATA_VALUE_LEN + 1);
+        for (key, value) in [
+            ("", "v"),
+            ("-key", "v"),
+            ("key.", "v"),
+            ("a key", "v"),
+            ("meta/key", "v"),
+            (long_key.as_str(), "v"),
+            ("key", long_value.as_str()),
+        ] {
+            assert!(
+                matches!(
+                    validate_metadata(&metadata(&[(key, value)])),
+                    Err(Error::InvalidMetadata(k)) if k == key
+                ),
+                "{}",
+                key
+            );
+        }
+
+        let too_many: BTreeMap<String, String> = (0..=MAX_METADATA)
+            .map(|i| (format!("k{}", i), String::new()))
+            .collect();
+        assert!(matches!(
+            validate_metadata(&too_many),
+            Err(Error::LimitExceeded(_))
+        ));
+    }
+
+    #[test]
+    fn test_search_params() {
+        let user = claims("user.example", None);
+        assert_eq!(
+            search_params(&user, false).label_selector,
+            Some(format!(
+                "{}={}",
+                CREATOR_LABEL,
+                creator_label("user.example")
+            ))
+        );
+        let member = claims("user.example", Some("team.example"));
+        assert_eq!(
+            search_params(&member, false).label_selector,
+            Some(format!("{}=team.example", GROUP_LABEL))
+        );
+        // Admins search everything.
+        assert_eq!(search_params(&member, true).label_selector, None);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        let secs = |s| parse_duration(s).map(|d| d.num_seconds());
+        assert_eq!(secs("90s"), Some(90));
+        assert_eq!(secs("30m"), Some(30 * 60));
+        assert_eq!(secs("2h"), Some(2 * 60 * 60));
+        assert_eq!(secs("1d"), Some(24 * 60 * 60));
+        assert_eq!(secs("1h30m"), Some(90 * 60));
+        assert_eq!(secs(" 0s "), Some(0));
+        assert_eq!(secs(""), None);
+        assert_eq!(secs("30"), None);
+        assert_eq!(secs("m"), None);
+        assert_eq!(secs("1w"), None);
+        assert_eq!(secs("-1h"), None);
+        assert_eq!(secs("1h 30m"), None);
+        assert_eq!(secs(&format!("{}d", u64::MAX)), None);
+    }
+}
@@ -0,0 +1,81 @@
+// Localized error messages.
+// Errors with a `code` are rendered in the language requested with `Accept-Language`.
+use warp::{http::StatusCode, reply};
+
+// Supported languages. The first one is the default.
+const LANGUAGES: &[&str] = &["en", "es", "ja"];
+
+// Messages for each code in the order of `LANGUAGES`. `{0}` is replaced with the argument.
+const CATALOG: &[(&str, [&str; 3])] = &[
+    (
+        "preset_not_found",
+        [
+            "preset {0} not found",
+            "no se encontró el preajuste {0}",
+            "プリセット {0} が見つかりません",
+        ],
+    ),
+    (
+        "invalid_lifetime",
+        [
+            "lifetime {0} is invalid",
+            "la duración {0} no es válida",
+            "有効期間 {0} は無効です",
+        ],
+    ),
+    (
+        "forbidden",
+        ["Forbidden", "Prohibido", "アクセスが禁止されています"],
+    ),
+];
+
+/// Create an error response with `code` and its message in the default language.
+/// `code` must be in the catalog.
+pub(super) fn error_response(
+    code: &'static str,
+    arg: Option<String>,
+    status: StatusCode,
+) -> reply::Response {
+    let message = message(code, 0, arg.as_deref());
+    super::negotiate::error_response(Some(code), arg, message, status)
+}
+
+/// The message for `code` in the language at `lang` in `LANGUAGES`.
+pub(super) fn message(code: &str, lang: usize, arg: Option<&str>) -> String {
+    let template = CATALOG
+        .iter()
+        .find(|(c, _)| *c == code)
+        .map_or(code, |(_, messages)| messages[lang]);
+    match arg {
+        Some(arg) => template.replace("{0}", arg),
+        None => template.to_owned(),
+    }
+}
+
+/// Find the index of the most preferred supported language in `Accept-Language` header.
+/// e.g., `ja-JP,ja;q=0.9,en;q=0.8`
+pub(super) fn preferred_language(header: &str) -> usize {
+    let mut ranges: Vec<(&str, f32)> = header
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.trim().split(';');
+            let tag = parts.next()?.trim();
+            let q = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+            Some((tag, q))
+        })
+        .filter(|(tag, q)| !tag.is_empty() && *q > 0.0)
+        .collect();
+    // Stable sort keeps the order for the same quality.
+    ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    ranges
+        .iter()
+        .find_map(|(tag, _)| {
+            let primary = tag.split('-').next().unwrap_or_default();
+            LANGUAGES
+                .iter()
+                .position(|l| l.eq_ignore_ascii_case(primary))
+        })
+        .unwrap_or(0)
+}
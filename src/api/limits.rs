@@ -0,0 +1,128 @@
+// Limits on the size of the spec of the services created with the API.
+use std::collections::BTreeMap;
+
+use k8s_openapi::{
+    api::core::v1::ResourceRequirements, apimachinery::pkg::api::resource::Quantity,
+};
+
+use crate::EphemeronSpec;
+
+/// Limits on the size of the spec. Unlimited if unset.
+#[derive(Debug, serde::Deserialize, Clone, Default)]
+pub struct Limits {
+    /// Maximum number of `env` entries.
+    pub max_env: Option<usize>,
+    /// Maximum number of `pod_labels`.
+    pub max_pod_labels: Option<usize>,
+    /// Maximum size of the spec in bytes when serialized as JSON.
+    pub max_spec_bytes: Option<usize>,
+    /// Maximum quantity of each resource (e.g., `cpu`, `memory`) that can be requested with patch.
+    /// Resources not listed can't be requested.
+    #[serde(default)]
+    pub max_resources: BTreeMap<String, Quantity>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{field} exceeds the limit of {max}")]
+pub(super) struct Exceeded {
+    pub(super) field: &'static str,
+    pub(super) max: usize,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(super) enum InvalidResources {
+    #[error("{0} is not allowed")]
+    NotAllowed(String),
+
+    #[error("{0} is invalid")]
+    InvalidQuantity(String),
+
+    #[error("{0} exceeds the maximum {1}")]
+    Exceeded(String, String),
+
+    #[error("requests.{0} exceeds limits.{0}")]
+    RequestOverLimit(String),
+}
+
+/// Check that `resources` are within `max`.
+pub(super) fn validate_resources(
+    resources: &ResourceRequirements,
+    max: &BTreeMap<String, Quantity>,
+) -> Result<(), InvalidResources> {
+    let limits = resources.limits.iter().flatten().map(|r| ("limits", r));
+    let requests = resources.requests.iter().flatten().map(|r| ("requests", r));
+    for (kind, (name, quantity)) in limits.chain(requests) {
+        let field = format!("{}.{}", kind, name);
+        let max = max
+            .get(name)
+            .ok_or_else(|| InvalidResources::NotAllowed(field.clone()))?;
+        let value = parse_quantity(&quantity.0)
+            .ok_or_else(|| InvalidResources::InvalidQuantity(field.clone()))?;
+        if parse_quantity(&max.0).map_or(true, |max| value > max) {
+            return Err(InvalidResources::Exceeded(field, max.0.clone()));
+        }
+    }
+    for (name, request) in resources.requests.iter().flatten() {
+        let limit = resources.limits.as_ref().and_then(|ls| ls.get(name));
+        if let (Some(request), Some(limit)) = (
+            parse_quantity(&request.0),
+            limit.and_then(|l| parse_quantity(&l.0)),
+        ) {
+            if request > limit {
+                return Err(InvalidResources::RequestOverLimit(name.clone()));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Parse the quantity like `500m`, `1.5`, `2Gi`, or `1e3` as a number.
+fn parse_quantity(s: &str) -> Option<f64> {
+    const SUFFIXES: &[(&str, f64)] = &[
+        ("Ki", 1024.0),
+        ("Mi", 1_048_576.0),
+        ("Gi", 1_073_741_824.0),
+        ("Ti", 1_099_511_627_776.0),
+        ("Pi", 1_125_899_906_842_624.0),
+        ("Ei", 1_152_921_504_606_846_976.0),
+        ("n", 1e-9),
+        ("u", 1e-6),
+        ("m", 1e-3),
+        ("k", 1e3),
+        ("M", 1e6),
+        ("G", 1e9),
+        ("T", 1e12),
+        ("P", 1e15),
+        ("E", 1e18),
+    ];
+    let s = s.trim();
+    let (number, multiplier) = SUFFIXES
+        .iter()
+        .find_map(|(suffix, m)| s.strip_suffix(suffix).map(|n| (n, *m)))
+        .unwrap_or((s, 1.0));
+    let value = number.parse::<f64>().ok()? * multiplier;
+    (value.is_finite() && value >= 0.0).then(|| value)
+}
+
+/// Check that `spec` is within `limits`.
+pub(super) fn validate(spec: &EphemeronSpec, limits: &Limits) -> Result<(), Exceeded> {
+    let env = spec.service.env.as_ref().map_or(0, Vec::len);
+    check("env", env, limits.max_env)?;
+    check(
+        "podLabels",
+        spec.service.pod_labels.len(),
+        limits.max_pod_labels,
+    )?;
+    if let Some(max) = limits.max_spec_bytes {
+        let size = serde_json::to_vec(spec).map_or(usize::MAX, |v| v.len());
+        check("spec", size, Some(max))?;
+    }
+    Ok(())
+}
+
+fn check(field: &'static str, n: usize, max: Option<usize>) -> Result<(), Exceeded> {
+    match max {
+        Some(max) if n > max => Err(Exceeded { field, max }),
+        _ => Ok(()),
+    }
+}
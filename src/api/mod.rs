@@ -1,11 +1,23 @@
 // Simple Web API for Ephemeron.
-use std::{convert::Infallible, error::Error, sync::Arc};
+use std::{
+    convert::Infallible,
+    error::Error,
+    sync::{Arc, PoisonError, RwLock},
+};
 
-use kube::Client;
+use kube::{core::admission::AdmissionReview, Client};
 use warp::{http::StatusCode, reply, Filter, Rejection, Reply};
 
+mod admin;
+mod admission;
 mod auth;
+mod exec;
 mod handlers;
+mod i18n;
+mod limits;
+mod negotiate;
+mod pool;
+mod readiness;
 
 #[derive(Debug, serde::Deserialize, Clone)]
 pub struct Config {
@@ -13,9 +25,183 @@ pub struct Config {
     pub presets: Presets,
     /// Map of known `app`s to its `key`s.
     pub apps: auth::Apps,
+    /// Number of ready services to keep for presets, so they can be handed out immediately.
+    #[serde(default)]
+    pub pool: std::collections::BTreeMap<String, usize>,
+    /// The domain used by the controller. Hosts `{id}.{domain}` are validated on create if set.
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// The namespace of the Pods created by the controller.
+    #[serde(default = "default_namespace")]
+    pub namespace: String,
+    /// Create the services in `ephemeron-{app}` namespace of the creator's app instead of `namespace`.
+    #[serde(default)]
+    pub namespace_per_app: bool,
+    /// The minimum `lifetimeMinutes` to create a service with.
+    #[serde(default = "default_min_lifetime_minutes")]
+    pub min_lifetime_minutes: u32,
+    /// The maximum `lifetimeMinutes` for presets without their own. Unlimited if not set.
+    #[serde(default)]
+    pub max_lifetime_minutes: Option<u32>,
+    /// Limits on the size of the spec of the services to create.
+    #[serde(default)]
+    pub limits: Limits,
+    /// Apps allowed to change the resources of the services with patch.
+    #[serde(default)]
+    pub resize_apps: std::collections::BTreeSet<String>,
+    /// Hosts allowed in `callbackUrl`. Callbacks are rejected unless set.
+    #[serde(default)]
+    pub callback_hosts: std::collections::BTreeSet<String>,
+    /// Presets whose `command` must not contain shell metacharacters. Checked by the admission webhook.
+    #[serde(default)]
+    pub strict_command_presets: std::collections::BTreeSet<String>,
+    /// Subjects (`uid.app`) allowed to use admin routes.
+    #[serde(default)]
+    pub admins: std::collections::BTreeSet<String>,
+    /// How long the tokens from `POST /auth` are valid for in seconds.
+    #[serde(default = "default_token_ttl_seconds")]
+    pub token_ttl_seconds: u32,
+    /// The maximum size of the request bodies in bytes. Only read at startup.
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: u64,
+    /// Path to the PEM encoded certificate to serve over TLS. Requires `tls_key_path`.
+    #[serde(default)]
+    pub tls_cert_path: Option<std::path::PathBuf>,
+    /// Path to the PEM encoded private key to serve over TLS. Requires `tls_cert_path`.
+    #[serde(default)]
+    pub tls_key_path: Option<std::path::PathBuf>,
 }
 
-pub type Presets = std::collections::BTreeMap<String, crate::EphemeronService>;
+pub use auth::{load_keys, KeyError};
+pub use limits::Limits;
+
+fn default_namespace() -> String {
+    "default".to_owned()
+}
+
+fn default_min_lifetime_minutes() -> u32 {
+    1
+}
+
+fn default_token_ttl_seconds() -> u32 {
+    300
+}
+
+fn default_max_body_bytes() -> u64 {
+    1024 * 1024
+}
+
+const TOKEN_TTL_SECONDS: std::ops::RangeInclusive<u32> = 60..=86400;
+
+pub type Presets = std::collections::BTreeMap<String, Preset>;
+
+/// Predefined service with the limits for it.
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Preset {
+    #[serde(flatten)]
+    pub service: crate::EphemeronService,
+    /// The maximum `lifetimeMinutes`. Defaults to `max_lifetime_minutes` in config.
+    #[serde(default)]
+    pub max_lifetime_minutes: Option<u32>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("pool for unknown preset {0}")]
+    UnknownPoolPreset(String),
+
+    #[error("pool for preset {0} must be named with a valid label value")]
+    InvalidPoolPreset(String),
+
+    #[error("pool can't be used with namespace_per_app")]
+    PoolWithNamespacePerApp,
+
+    #[error("app name {0} is longer than {} characters", auth::MAX_APP_LEN)]
+    AppNameTooLong(String),
+
+    #[error("hosts would be invalid DNS names with domain {0}")]
+    InvalidDomain(String),
+
+    #[error(
+        "token_ttl_seconds {0} must be between {} and {}",
+        TOKEN_TTL_SECONDS.start(),
+        TOKEN_TTL_SECONDS.end()
+    )]
+    InvalidTokenTtl(u32),
+}
+
+impl Config {
+    /// The maximum `lifetimeMinutes` for the preset. `None` if unlimited.
+    fn max_lifetime_minutes(&self, preset: Option<&str>) -> Option<u32> {
+        preset
+            .and_then(|name| self.presets.get(name))
+            .and_then(|p| p.max_lifetime_minutes)
+            .or(self.max_lifetime_minutes)
+    }
+
+    /// Check that the config is usable.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError`] describing the first problem found.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(name) = self.pool.keys().find(|&n| !self.presets.contains_key(n)) {
+            return Err(ConfigError::UnknownPoolPreset(name.clone()));
+        }
+        // Pooled ones are labeled with the name of the preset.
+        if let Some(name) = self
+            .pool
+            .keys()
+            .find(|&n| !crate::resource::is_label_value(n))
+        {
+            return Err(ConfigError::InvalidPoolPreset(name.clone()));
+        }
+        // Pooled ones are created before the app creating it is known.
+        if self.namespace_per_app && !self.pool.is_empty() {
+            return Err(ConfigError::PoolWithNamespacePerApp);
+        }
+        if let Some(app) = self.apps.keys().find(|a| a.len() > auth::MAX_APP_LEN) {
+            return Err(ConfigError::AppNameTooLong(app.clone()));
+        }
+        if !TOKEN_TTL_SECONDS.contains(&self.token_ttl_seconds) {
+            return Err(ConfigError::InvalidTokenTtl(self.token_ttl_seconds));
+        }
+        if let Some(domain) = &self.domain {
+            let host = format!("{}.{}", "x".repeat(crate::resource::ID_LEN), domain);
+            if !crate::resource::is_valid_host(&host) {
+                return Err(ConfigError::InvalidDomain(domain.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Config shared by the handlers that can be replaced while running.
+/// Each request uses the snapshot at the time it was received.
+#[derive(Debug, Clone)]
+pub struct SharedConfig(Arc<RwLock<Arc<Config>>>);
+
+impl SharedConfig {
+    #[must_use]
+    pub fn new(config: Config) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(config))))
+    }
+
+    /// The current config.
+    #[must_use]
+    pub fn get(&self) -> Arc<Config> {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Replace the config atomically.
+    pub fn replace(&self, config: Config) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(config);
+    }
+}
 
 /// Payload for creating service with a preset.
 #[derive(serde::Deserialize, Debug, PartialEq, Clone)]
@@ -23,20 +209,86 @@ pub type Presets = std::collections::BTreeMap<String, crate::EphemeronService>;
 struct PresetPayload {
     /// The name of the preset to use.
     pub preset: String,
-    /// The duration to expire the service after in minutes.
-    pub lifetime_minutes: u32,
+    /// The duration to expire the service after. Required.
+    #[serde(flatten)]
+    pub lifetime: Lifetime,
+    /// Arbitrary key/value pairs to find the service later.
+    #[serde(default)]
+    pub metadata: std::collections::BTreeMap<String, String>,
+    /// URL to notify once the service becomes available.
+    pub callback_url: Option<String>,
 }
 
-/// Payload for patching expiry.
+/// Payload for patching expiry and resources.
 #[derive(serde::Deserialize, Debug, PartialEq, Clone)]
 #[serde(rename_all = "camelCase")]
 struct PatchPayload {
-    /// The new duration to expire after from now in minutes.
-    pub lifetime_minutes: u32,
+    /// The new duration to expire after from now.
+    #[serde(flatten)]
+    pub lifetime: Lifetime,
+    /// The new time to expire at. Takes precedence over `lifetime`.
+    pub expiration_time: Option<chrono::DateTime<chrono::Utc>>,
+    /// The new compute resources. Replaces the current ones, and the Pod is recreated.
+    pub resources: Option<k8s_openapi::api::core::v1::ResourceRequirements>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct ListQuery {
+    /// Include the services in the caller's group.
+    #[serde(default)]
+    all: bool,
+    /// The maximum number of resources to list in a page.
+    limit: Option<u32>,
+    /// The token from the previous page to continue from.
+    #[serde(rename = "continue")]
+    continue_: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct LogsQuery {
+    /// Only the last lines. All by default.
+    tail: Option<u32>,
+    /// Keep streaming new lines.
+    #[serde(default)]
+    follow: bool,
+}
+
+/// Payload for patching expiry of multiple services.
+#[derive(serde::Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+struct LifetimePayload {
+    /// The new duration to expire after from now. Required.
+    #[serde(flatten)]
+    pub lifetime: Lifetime,
+}
+
+/// The duration to expire after in either field. `duration` takes precedence.
+#[derive(serde::Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+struct Lifetime {
+    /// In minutes.
+    pub lifetime_minutes: Option<u32>,
+    /// Like `90m`, `2h`, or `1h30m`.
+    pub duration: Option<String>,
+}
+
+impl Lifetime {
+    /// The requested value for error messages.
+    fn input(&self) -> String {
+        match (&self.duration, self.lifetime_minutes) {
+            (Some(duration), _) => duration.clone(),
+            (None, Some(minutes)) => minutes.to_string(),
+            (None, None) => String::new(),
+        }
+    }
 }
 
 #[derive(serde::Serialize)]
 struct ErrorMessage {
+    /// Machine-readable code of the error.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+    /// Human-readable message.
     message: String,
 }
 
@@ -48,67 +300,217 @@ fn json_error_response<T: Into<String>>(
     message: T,
     status: warp::http::StatusCode,
 ) -> reply::Response {
-    reply::with_status(
-        reply::json(&ErrorMessage {
-            message: message.into(),
-        }),
-        status,
-    )
-    .into_response()
+    negotiate::error_response(None, None, message.into(), status)
+}
+
+/// Keep the pool of ready services configured in `config`. Never returns.
+pub async fn maintain_pool(client: Client, config: SharedConfig) {
+    pool::maintain(client, config).await;
 }
 
 #[must_use]
 pub fn new(
     client: Client,
-    config: Config,
+    config: SharedConfig,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    let presets = Arc::new(config.presets);
-    let apps = Arc::new(config.apps);
-    healthz()
-        .or(authenticate(apps))
-        .or(create(client.clone(), presets))
+    let routes = healthz()
+        .or(readyz(client.clone(), config.clone()))
+        .or(admission(config.clone()))
+        .or(list(client.clone()))
+        .or(authenticate(config.clone()))
+        .or(revoke())
+        .or(me())
+        .or(expiring(client.clone(), config.clone()))
+        .or(patch_group(client.clone(), config.clone()))
+        .or(create(client.clone(), config.clone()))
+        .or(search(client.clone(), config.clone()))
         .or(get(client.clone()))
-        .or(patch(client.clone()))
-        .or(delete(client))
-        .recover(handle_rejection)
+        .or(exec(client.clone(), config.clone()))
+        .or(logs(client.clone(), config.clone()))
+        .or(qr(client.clone()))
+        .or(share(client.clone()))
+        .or(pause(client.clone()))
+        .or(resume(client.clone()))
+        .or(patch(client.clone(), config))
+        .or(delete(client));
+    // Render errors with `Accept-Language` and `Accept`.
+    warp::header::optional::<String>("accept-language")
+        .and(warp::header::optional::<String>("accept"))
+        .and(routes.recover(handle_rejection))
+        .map(negotiate::render)
 }
 
-// GET /
+// GET /healthz
 fn healthz() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
-    warp::get().and(warp::path::end().map(|| "OK"))
+    warp::get()
+        .and(warp::path("healthz"))
+        .and(warp::path::end())
+        .map(|| "OK")
+}
+
+// GET /
+fn list(client: Client) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::get()
+        .and(warp::path::end())
+        .and(auth::filter::with_authorization())
+        .and(warp::query::<ListQuery>())
+        .and(with_client(client))
+        .and_then(handlers::list)
+}
+
+// GET /readyz
+fn readyz(
+    client: Client,
+    config: SharedConfig,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::get()
+        .and(warp::path("readyz"))
+        .and(warp::path::end())
+        .and(with_config(config))
+        .and(with_client(client))
+        .and_then(readiness::readyz)
+}
+
+// POST /admission
+fn admission(config: SharedConfig) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::post()
+        .and(warp::path("admission"))
+        .and(warp::path::end())
+        .and(json_body::<AdmissionReview<crate::Ephemeron>>(&config))
+        .and(with_config(config))
+        .and_then(admission::validate)
 }
 
 // POST /
 fn create(
     client: Client,
-    presets: Arc<Presets>,
+    config: SharedConfig,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
     warp::post()
         .and(warp::path::end())
         .and(auth::filter::with_authorization())
-        .and(json_body::<PresetPayload>())
-        .and(warp::any().map(move || presets.clone()))
+        .and(json_body::<PresetPayload>(&config))
+        .and(with_config(config))
         .and(with_client(client))
         .and_then(handlers::create)
 }
 
+// POST /:id/share
+fn share(client: Client) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::post()
+        .and(warp::path::param::<String>())
+        .and(warp::path("share"))
+        .and(warp::path::end())
+        .and(auth::filter::with_authorization())
+        .and(with_client(client))
+        .and_then(handlers::share)
+}
+
+// GET /:id/logs
+fn logs(
+    client: Client,
+    config: SharedConfig,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::get()
+        .and(warp::path::param::<String>())
+        .and(warp::path("logs"))
+        .and(warp::path::end())
+        .and(auth::filter::with_authorization())
+        .and(warp::query::<LogsQuery>())
+        .and(with_config(config))
+        .and(with_client(client))
+        .and_then(handlers::logs)
+}
+
+// POST /:id/pause
+fn pause(client: Client) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::post()
+        .and(warp::path::param::<String>())
+        .and(warp::path("pause"))
+        .and(warp::path::end())
+        .and(auth::filter::with_authorization())
+        .and(with_client(client))
+        .and_then(handlers::pause)
+}
+
+// POST /:id/resume
+fn resume(client: Client) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::post()
+        .and(warp::path::param::<String>())
+        .and(warp::path("resume"))
+        .and(warp::path::end())
+        .and(auth::filter::with_authorization())
+        .and(with_client(client))
+        .and_then(handlers::resume)
+}
+
+// GET /:id/qr
+fn qr(client: Client) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::get()
+        .and(warp::path::param::<String>())
+        .and(warp::path("qr"))
+        .and(warp::path::end())
+        .and(auth::filter::with_any_authorization())
+        .and(with_client(client))
+        .and_then(handlers::qr)
+}
+
+// GET /:id/exec
+fn exec(
+    client: Client,
+    config: SharedConfig,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::get()
+        .and(warp::path::param::<String>())
+        .and(warp::path("exec"))
+        .and(warp::path::end())
+        // Browsers can't set headers for WebSocket.
+        .and(auth::filter::with_authorization_or_query())
+        .and(warp::query::<exec::ExecQuery>())
+        .and(warp::ws())
+        .and(with_config(config))
+        .and(with_client(client))
+        .and_then(exec::exec)
+}
+
 // PATCH /:id
-fn patch(client: Client) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+fn patch(
+    client: Client,
+    config: SharedConfig,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
     warp::patch()
         .and(warp::path::param::<String>())
         .and(warp::path::end())
         .and(auth::filter::with_authorization())
-        .and(json_body::<PatchPayload>())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(json_body::<PatchPayload>(&config))
+        .and(with_config(config))
         .and(with_client(client))
         .and_then(handlers::patch)
 }
 
+// GET /search?meta.{key}={value}
+// Must be before `GET /:id`.
+fn search(
+    client: Client,
+    config: SharedConfig,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::get()
+        .and(warp::path("search"))
+        .and(warp::path::end())
+        .and(auth::filter::with_authorization())
+        .and(warp::query::<std::collections::BTreeMap<String, String>>())
+        .and(with_config(config))
+        .and(with_client(client))
+        .and_then(handlers::search)
+}
+
 // GET /:id
 fn get(client: Client) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
     warp::get()
         .and(warp::path::param::<String>())
         .and(warp::path::end())
-        .and(auth::filter::with_authorization())
+        .and(auth::filter::with_any_authorization())
         .and(with_client(client))
         .and_then(handlers::get)
 }
@@ -123,41 +525,109 @@ fn delete(client: Client) -> impl Filter<Extract = impl Reply, Error = Rejection
         .and_then(handlers::delete)
 }
 
+// GET /admin/expiring?within=1h
+fn expiring(
+    client: Client,
+    config: SharedConfig,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::get()
+        .and(warp::path("admin"))
+        .and(warp::path("expiring"))
+        .and(warp::path::end())
+        .and(auth::filter::with_authorization())
+        .and(warp::query::<admin::ExpiringQuery>())
+        .and(with_config(config))
+        .and(with_client(client))
+        .and_then(admin::expiring)
+}
+
+// PATCH /admin/ephemerons?group={gid}
+fn patch_group(
+    client: Client,
+    config: SharedConfig,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::patch()
+        .and(warp::path("admin"))
+        .and(warp::path("ephemerons"))
+        .and(warp::path::end())
+        .and(auth::filter::with_authorization())
+        .and(warp::query::<admin::GroupQuery>())
+        .and(json_body::<LifetimePayload>(&config))
+        .and(with_config(config))
+        .and(with_client(client))
+        .and_then(admin::patch_group)
+}
+
 // POST /auth
 fn authenticate(
-    apps: Arc<auth::Apps>,
+    config: SharedConfig,
 ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let body = json_body::<auth::TokenRequest>(&config);
     warp::post()
         .and(warp::path("auth"))
         .and(warp::path::end())
-        .and(warp::any().map(move || apps.clone()))
-        .and(json_body::<auth::TokenRequest>())
+        .and(with_config(config))
+        .and(body)
         .and_then(auth::token)
 }
 
+// DELETE /auth
+fn revoke() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::delete()
+        .and(warp::path("auth"))
+        .and(warp::path::end())
+        .and(auth::filter::with_any_authorization())
+        .and_then(auth::revoke)
+}
+
+// GET /auth/me
+fn me() -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    warp::get()
+        .and(warp::path("auth"))
+        .and(warp::path("me"))
+        .and(warp::path::end())
+        .and(auth::filter::with_authorization())
+        .and_then(auth::me)
+}
+
+fn with_config(
+    config: SharedConfig,
+) -> impl Filter<Extract = (Arc<Config>,), Error = Infallible> + Clone {
+    warp::any().map(move || config.get())
+}
+
 fn with_client(client: Client) -> impl Filter<Extract = (Client,), Error = Infallible> + Clone {
     warp::any().map(move || client.clone())
 }
 
-fn json_body<T>() -> impl Filter<Extract = (T,), Error = Rejection> + Clone
+// The limit is read when the routes are created.
+fn json_body<T>(config: &SharedConfig) -> impl Filter<Extract = (T,), Error = Rejection> + Clone
 where
     T: serde::de::DeserializeOwned + Send,
 {
-    warp::body::content_length_limit(1024 * 1024).and(warp::body::json())
+    warp::body::content_length_limit(config.get().max_body_bytes).and(warp::body::json())
 }
 
 #[allow(clippy::unused_async)]
 async fn handle_rejection(err: Rejection) -> Result<impl Reply, Rejection> {
     let (message, status) = if err.is_not_found() {
         ("Not Found", StatusCode::NOT_FOUND)
+    } else if let Some(auth::filter::Error::Restricted) = err.find::<auth::filter::Error>() {
+        ("Forbidden", StatusCode::FORBIDDEN)
     } else if err.find::<auth::filter::Error>().is_some() {
         ("Unauthorized", StatusCode::UNAUTHORIZED)
     } else if let Some(e) = err.find::<warp::filters::body::BodyDeserializeError>() {
-        // TODO Improve error message. e.g., "missing field `duration`"
-        if let Some(cause) = e.source() {
-            tracing::debug!("deserialize error: {:?}", cause);
-        }
-        ("Bad Request", StatusCode::BAD_REQUEST)
+        let reason = e.source().map_or_else(
+            || "invalid body".to_owned(),
+            |cause| {
+                tracing::debug!("deserialize error: {:?}", cause);
+                deserialize_reason(cause)
+            },
+        );
+        return Ok(json_error_response(
+            format!("Bad Request: {}", reason),
+            StatusCode::BAD_REQUEST,
+        ));
     } else if err.find::<warp::reject::PayloadTooLarge>().is_some() {
         ("Payload Too Large", StatusCode::PAYLOAD_TOO_LARGE)
     } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
@@ -169,3 +639,133 @@ async fn handle_rejection(err: Rejection) -> Result<impl Reply, Rejection> {
 
     Ok(json_error_response(message, status))
 }
+
+// Human-readable reason of the deserialize error, e.g., "missing field `preset`".
+// The position and the names of the internal types are removed.
+fn deserialize_reason(cause: &(dyn Error + 'static)) -> String {
+    let err = match cause.downcast_ref::<serde_json::Error>() {
+        Some(err) => err,
+        None => return "invalid body".to_owned(),
+    };
+    if err.is_eof() {
+        return "unexpected end of body".to_owned();
+    }
+    if !err.is_data() {
+        return "invalid JSON".to_owned();
+    }
+    let message = err.to_string();
+    let message = message
+        .rsplit_once(" at line ")
+        .map_or(message.as_str(), |(m, _)| m);
+    // e.g., "invalid type: integer `1`, expected struct PresetPayload"
+    match message.find("expected struct ") {
+        Some(i) => format!("{}expected an object", &message[..i]),
+        None => message.to_owned(),
+    }
+}
+
+/// The config with `apps`, `presets`, and the rest from `yaml` to use in tests.
+#[cfg(test)]
+pub(crate) fn test_config(yaml: &str) -> Config {
+    let mut config: serde_yaml::Mapping =
+        serde_yaml::from_str("apps: {example: key}\npresets: {nginx: {image: nginx, port: 80}}")
+            .unwrap();
+    let overrides: serde_yaml::Mapping = serde_yaml::from_str(yaml).unwrap();
+    config.extend(overrides);
+    serde_yaml::from_value(serde_yaml::Value::Mapping(config)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(test_config("{}").validate().is_ok());
+        assert!(test_config("pool: {nginx: 1}").validate().is_ok());
+        assert!(matches!(
+            test_config("pool: {redis: 1}").validate(),
+            Err(ConfigError::UnknownPoolPreset(_))
+        ));
+        assert!(matches!(
+            test_config("pool: {nginx: 1}\nnamespace_per_app: true").validate(),
+            Err(ConfigError::PoolWithNamespacePerApp)
+        ));
+        assert!(test_config("namespace_per_app: true").validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_app_name() {
+        let apps = |len: usize| test_config(&format!("apps: {{{}: key}}", "x".repeat(len)));
+        assert!(apps(auth::MAX_APP_LEN).validate().is_ok());
+        assert!(matches!(
+            apps(auth::MAX_APP_LEN + 1).validate(),
+            Err(ConfigError::AppNameTooLong(_))
+        ));
+        assert_eq!(auth::MAX_APP_LEN, 61);
+    }
+
+    // `POST` with `json_body` of `PresetPayload` limited to `max_body_bytes`.
+    fn post_json(
+        max_body_bytes: u64,
+    ) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+        let config = SharedConfig::new(test_config(&format!("max_body_bytes: {}", max_body_bytes)));
+        warp::post()
+            .and(json_body::<PresetPayload>(&config))
+            .map(|_| "OK")
+            .recover(handle_rejection)
+    }
+
+    #[tokio::test]
+    async fn test_json_body_limit() {
+        let body = r#"{"preset": "nginx", "lifetimeMinutes": 5}"#;
+        let res = warp::test::request()
+            .method("POST")
+            .body(body)
+            .reply(&post_json(body.len() as u64))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = warp::test::request()
+            .method("POST")
+            .body(body)
+            .reply(&post_json(body.len() as u64 - 1))
+            .await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let message: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(message["message"], "Payload Too Large");
+    }
+
+    async fn bad_request(body: &str) -> String {
+        let res = warp::test::request()
+            .method("POST")
+            .body(body)
+            .reply(&post_json(1024))
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", body);
+        let message: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        message["message"].as_str().unwrap().to_owned()
+    }
+
+    #[tokio::test]
+    async fn test_deserialize_reason() {
+        assert_eq!(
+            bad_request(r#"{"lifetimeMinutes": 5}"#).await,
+            "Bad Request: missing field `preset`"
+        );
+        assert_eq!(
+            bad_request(r#"{"preset": "nginx", "lifetimeMinutes": "5"}"#).await,
+            "Bad Request: invalid type: string \"5\", expected u32"
+        );
+        // Without the name of the type.
+        assert_eq!(
+            bad_request("1").await,
+            "Bad Request: invalid type: integer `1`, expected an object"
+        );
+        assert_eq!(
+            bad_request(r#"{"preset": "nginx""#).await,
+            "Bad Request: unexpected end of body"
+        );
+        assert_eq!(bad_request("{preset}").await, "Bad Request: invalid JSON");
+    }
+}
@@ -0,0 +1,112 @@
+// Render error responses based on the request headers.
+// `Accept: application/problem+json` responds with RFC 7807 Problem Details,
+// and `Accept-Language` localizes the messages of the errors with a `code`.
+use warp::{
+    http::{header, HeaderValue, StatusCode},
+    reply, Reply,
+};
+
+use super::{i18n, ErrorMessage};
+
+const PROBLEM_JSON: &str = "application/problem+json";
+
+// Attached to the error response to render it again later.
+#[derive(Debug, Clone)]
+struct ErrorDetail {
+    code: Option<&'static str>,
+    arg: Option<String>,
+    message: String,
+}
+
+/// RFC 7807 Problem Details.
+#[derive(serde::Serialize)]
+struct Problem {
+    #[serde(rename = "type")]
+    type_: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+}
+
+/// Create the default error response `{message, code?}`. The message must be in the default language.
+pub(super) fn error_response(
+    code: Option<&'static str>,
+    arg: Option<String>,
+    message: String,
+    status: StatusCode,
+) -> reply::Response {
+    let mut res = reply::with_status(
+        reply::json(&ErrorMessage {
+            code,
+            message: message.clone(),
+        }),
+        status,
+    )
+    .into_response();
+    res.extensions_mut()
+        .insert(ErrorDetail { code, arg, message });
+    res
+}
+
+/// Render the error in `res` in the format and the language preferred by the client.
+#[allow(clippy::needless_pass_by_value)]
+pub(super) fn render<R: Reply>(
+    accept_language: Option<String>,
+    accept: Option<String>,
+    res: R,
+) -> reply::Response {
+    let res = res.into_response();
+    let problem = accept.as_deref().map_or(false, accepts_problem);
+    let lang = accept_language
+        .as_deref()
+        .map_or(0, i18n::preferred_language);
+    let detail = match res.extensions().get::<ErrorDetail>() {
+        Some(detail) if problem || (lang != 0 && detail.code.is_some()) => detail,
+        _ => return res,
+    };
+
+    let message = match detail.code {
+        Some(code) => i18n::message(code, lang, detail.arg.as_deref()),
+        None => detail.message.clone(),
+    };
+    let status = res.status();
+    if problem {
+        let mut res = reply::with_status(
+            reply::json(&Problem {
+                type_: "about:blank",
+                title: status.canonical_reason().unwrap_or_default(),
+                status: status.as_u16(),
+                detail: message,
+                code: detail.code,
+            }),
+            status,
+        )
+        .into_response();
+        res.headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+        res
+    } else {
+        reply::with_status(
+            reply::json(&ErrorMessage {
+                code: detail.code,
+                message,
+            }),
+            status,
+        )
+        .into_response()
+    }
+}
+
+// `true` if `application/problem+json` is in `Accept` header with a non-zero quality.
+fn accepts_problem(header: &str) -> bool {
+    header.split(',').any(|range| {
+        let mut parts = range.trim().split(';');
+        let media = parts.next().unwrap_or_default().trim();
+        let q = parts
+            .find_map(|p| p.trim().strip_prefix("q="))
+            .map_or(Some(1.0), |q| q.parse::<f32>().ok());
+        media.eq_ignore_ascii_case(PROBLEM_JSON) && q.map_or(false, |q| q > 0.0)
+    })
+}
@@ -0,0 +1,139 @@
+// Pool of ready services to hand out on create without waiting for them to start.
+use std::{collections::BTreeMap, time::Duration};
+
+use chrono::{DateTime, Utc};
+use kube::{
+    api::{ListParams, Patch, PatchParams, PostParams},
+    Api, Client, ResourceExt,
+};
+
+use super::{auth::Claims, SharedConfig};
+use crate::{
+    resource::{
+        creator_label, APP_LABEL, CREATED_BY, CREATOR_LABEL, GROUP_LABEL, PRESET_ANNOTATION,
+    },
+    Ephemeron, EphemeronSpec,
+};
+
+// Label added to unclaimed services in the pool. The value is the name of the preset.
+// Removed when claimed.
+const POOL_LABEL: &str = "ephemerons.qualified.io/pool";
+// How long to keep unclaimed services. Expired ones are deleted by the controller and replaced.
+const POOL_LIFETIME_MINUTES: i64 = 60;
+// How often to check the pool.
+const POOL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Keep the configured number of services for each preset in the pool. Never returns.
+pub(super) async fn maintain(client: Client, config: SharedConfig) {
+    let api: Api<Ephemeron> = Api::all(client);
+    loop {
+        let config = config.get();
+        for (name, &size) in &config.pool {
+            if let Some(preset) = config.presets.get(name) {
+                if let Err(err) = fill(&api, name, &preset.service, size).await {
+                    tracing::warn!("failed to fill pool for {}: {}", name, err);
+                }
+            } else {
+                tracing::warn!("pool for unknown preset {}", name);
+            }
+        }
+        tokio::time::sleep(POOL_INTERVAL).await;
+    }
+}
+
+async fn fill(
+    api: &Api<Ephemeron>,
+    name: &str,
+    preset: &crate::EphemeronService,
+    size: usize,
+) -> Result<(), kube::Error> {
+    let now = Utc::now();
+    let pooled = list(api, name).await?;
+    let current = pooled
+        .iter()
+        .filter(|e| e.spec.expiration_time > now)
+        .count();
+    for _ in current..size {
+        let mut eph = Ephemeron::new(
+            &xid::new().to_string(),
+            EphemeronSpec {
+                expiration_time: now + chrono::Duration::minutes(POOL_LIFETIME_MINUTES),
+                service: preset.clone(),
+                paused: false,
+                callback_url: None,
+            },
+        );
+        eph.labels_mut()
+            .insert(POOL_LABEL.to_owned(), name.to_owned());
+        eph.annotations_mut()
+            .insert(PRESET_ANNOTATION.to_owned(), name.to_owned());
+        tracing::debug!("adding {} to the pool for {}", eph.name(), name);
+        api.create(&PostParams::default(), &eph).await?;
+    }
+    Ok(())
+}
+
+/// Claim a ready service in the pool for `preset` on behalf of the subject of `claims`.
+/// Returns `None` if there's none available, so the caller can create a new one.
+///
+/// The group label is added to the spec, but the running Pod is not relabeled.
+pub(super) async fn claim(
+    client: Client,
+    preset: &str,
+    claims: &Claims,
+    metadata: &BTreeMap<String, String>,
+    expiration_time: DateTime<Utc>,
+    callback_url: Option<&str>,
+) -> Option<Ephemeron> {
+    let api: Api<Ephemeron> = Api::all(client);
+    let pooled = match list(&api, preset).await {
+        Ok(pooled) => pooled,
+        Err(err) => {
+            tracing::warn!("failed to list pool for {}: {}", preset, err);
+            return None;
+        }
+    };
+
+    for eph in pooled.into_iter().filter(Ephemeron::is_available) {
+        let name = eph.name();
+        // Include `resourceVersion` so that the patch fails with a conflict
+        // if someone else claimed it first.
+        let mut patch = serde_json::json!({
+            "metadata": {
+                "resourceVersion": eph.resource_version(),
+                "labels": { POOL_LABEL: null, CREATOR_LABEL: creator_label(&claims.sub) },
+                "annotations": { CREATED_BY: claims.sub },
+            },
+            "spec": { "expirationTime": expiration_time },
+        });
+        for (key, value) in metadata {
+            patch["metadata"]["annotations"][key] = serde_json::json!(value);
+        }
+        if let Some(url) = callback_url {
+            patch["spec"]["callbackUrl"] = serde_json::json!(url);
+        }
+        if let Some(app) = claims.app() {
+            patch["metadata"]["labels"][APP_LABEL] = serde_json::json!(app);
+        }
+        if let Some(gid) = &claims.gid {
+            patch["metadata"]["labels"][GROUP_LABEL] = serde_json::json!(gid);
+            patch["spec"]["service"] = serde_json::json!({ "podLabels": { GROUP_LABEL: gid } });
+        }
+        let patch = Patch::Merge(patch);
+        match api.patch(&name, &PatchParams::default(), &patch).await {
+            Ok(eph) => {
+                tracing::debug!("claimed {} from the pool for {}", name, preset);
+                return Some(eph);
+            }
+            Err(err) => {
+                tracing::debug!("failed to claim {}: {}", name, err);
+            }
+        }
+    }
+    None
+}
+
+async fn list(api: &Api<Ephemeron>, preset: &str) -> Result<Vec<Ephemeron>, kube::Error> {
+    let lp = ListParams::default().labels(&format!("{}={}", POOL_LABEL, preset));
+    Ok(api.list(&lp).await?.items)
+}
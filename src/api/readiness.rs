@@ -0,0 +1,163 @@
+// Readiness checks for `GET /readyz`.
+use std::{collections::BTreeMap, convert::Infallible, sync::Arc, time::Duration};
+
+use kube::{api::ListParams, Api, Client};
+use warp::{http::StatusCode, Reply};
+
+use super::{json_response, Config};
+use crate::Ephemeron;
+
+// Fail the check instead of hanging the probe when the apiserver doesn't respond.
+const APISERVER_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Check {
+    /// The name of the check.
+    name: &'static str,
+    /// `true` if the check passed.
+    ok: bool,
+    /// The API is not ready if any critical check fails.
+    critical: bool,
+    /// The reason of the failure.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, critical: bool, result: Result<(), String>) -> Self {
+        Self {
+            name,
+            ok: result.is_ok(),
+            critical,
+            message: result.err(),
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Readiness {
+    ready: bool,
+    checks: Vec<Check>,
+}
+
+// `GET /readyz` -> `{ready: bool, checks: [{name: String, ok: bool, critical: bool, message?: String}]}`
+// Responds with 503 if any critical check fails.
+pub(super) async fn readyz(config: Arc<Config>, client: Client) -> Result<impl Reply, Infallible> {
+    let apiserver = apiserver(client).await;
+    Ok(respond(checks(&config, apiserver, jwt_keys())))
+}
+
+// All the checks in the order of the response. The results that need I/O are passed in.
+fn checks(
+    config: &Config,
+    apiserver: Result<(), String>,
+    jwt_keys: Result<(), String>,
+) -> Vec<Check> {
+    vec![
+        Check::new("apiserver", true, apiserver),
+        Check::new("jwtKeys", true, jwt_keys),
+        Check::new("presets", true, non_empty(&config.presets, "presets")),
+        Check::new("apps", false, non_empty(&config.apps, "apps")),
+    ]
+}
+
+fn respond(checks: Vec<Check>) -> warp::reply::Response {
+    let ready = checks.iter().all(|c| c.ok || !c.critical);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    json_response(&Readiness { ready, checks }, status)
+}
+
+async fn apiserver(client: Client) -> Result<(), String> {
+    let api: Api<Ephemeron> = Api::all(client);
+    match tokio::time::timeout(APISERVER_TIMEOUT, api.list(&ListParams::default().limit(1))).await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(err)) => {
+            tracing::warn!("failed to reach apiserver: {}", err);
+            Err("failed to reach apiserver".to_owned())
+        }
+        Err(_) => {
+            tracing::warn!("apiserver didn't respond in {:?}", APISERVER_TIMEOUT);
+            Err("apiserver timed out".to_owned())
+        }
+    }
+}
+
+fn jwt_keys() -> Result<(), String> {
+    super::auth::load_keys().map_err(|err| err.to_string())
+}
+
+fn non_empty<K, V>(map: &BTreeMap<K, V>, what: &str) -> Result<(), String> {
+    if map.is_empty() {
+        Err(format!("no {} configured", what))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn readiness(checks: Vec<Check>) -> (StatusCode, serde_json::Value) {
+        let res = respond(checks);
+        let status = res.status();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_ready() {
+        let config = super::super::test_config("{}");
+        let (status, body) = readiness(checks(&config, Ok(()), Ok(()))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "ready": true,
+                "checks": [
+                    {"name": "apiserver", "ok": true, "critical": true},
+                    {"name": "jwtKeys", "ok": true, "critical": true},
+                    {"name": "presets", "ok": true, "critical": true},
+                    {"name": "apps", "ok": true, "critical": false},
+                ],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_not_ready() {
+        let config = super::super::test_config("{}");
+        let (status, body) = readiness(checks(
+            &config,
+            Err("apiserver timed out".to_owned()),
+            Ok(()),
+        ))
+        .await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["ready"], false);
+        assert_eq!(body["checks"][0]["ok"], false);
+        assert_eq!(body["checks"][0]["message"], "apiserver timed out");
+        assert_eq!(body["checks"].as_array().unwrap().len(), 4);
+
+        let no_presets = super::super::test_config("presets: {}");
+        let (status, body) = readiness(checks(&no_presets, Ok(()), Ok(()))).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["checks"][2]["message"], "no presets configured");
+    }
+
+    #[tokio::test]
+    async fn test_ready_without_non_critical() {
+        let config = super::super::test_config("apps: {}");
+        let (status, body) = readiness(checks(&config, Ok(()), Ok(()))).await;
+        // Still ready, but reported.
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["ready"], true);
+        assert_eq!(body["checks"][3]["ok"], false);
+        assert_eq!(body["checks"][3]["message"], "no apps configured");
+    }
+}
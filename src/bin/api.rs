@@ -1,5 +1,7 @@
 // Provides Web API
-use ephemeron::api::Config;
+use std::time::Duration;
+
+use ephemeron::api::{Config, SharedConfig};
 use kube::Client;
 use thiserror::Error;
 use tracing_subscriber::fmt::format::FmtSpan;
@@ -8,6 +10,9 @@ use warp::{
     Filter,
 };
 
+// Don't let a slow config server block refreshing forever.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Failed to read config: {0}")]
@@ -15,6 +20,42 @@ pub enum Error {
 
     #[error("Failed to parse config: {0}")]
     ParseConfig(#[source] serde_yaml::Error),
+
+    #[error("Invalid config: {0}")]
+    InvalidConfig(#[source] ephemeron::api::ConfigError),
+
+    #[error("Invalid config URL: {0}")]
+    InvalidConfigUrl(#[source] hyper::http::uri::InvalidUri),
+
+    #[error("Failed to fetch config: {0}")]
+    FetchConfig(#[source] hyper::Error),
+
+    #[error("Failed to fetch config: status {0}")]
+    FetchConfigStatus(hyper::StatusCode),
+
+    #[error("Failed to fetch config: timed out")]
+    FetchConfigTimeout,
+
+    #[error("EPHEMERON_CONFIG_REFRESH_SECONDS must be a positive integer")]
+    InvalidRefreshInterval,
+
+    #[error("Failed to read TLS certificate: {0}")]
+    ReadTlsCert(#[source] std::io::Error),
+
+    #[error("Failed to read TLS private key: {0}")]
+    ReadTlsKey(#[source] std::io::Error),
+
+    #[error("Both tls_cert_path and tls_key_path must be set to use TLS")]
+    IncompleteTls,
+
+    #[error("No PEM encoded certificate in tls_cert_path")]
+    InvalidTlsCert,
+
+    #[error("No PEM encoded PKCS#8 or RSA private key in tls_key_path")]
+    InvalidTlsKey,
+
+    #[error("Invalid TLS certificate and key: {0}")]
+    InvalidTls(#[source] rustls::TLSError),
 }
 
 #[tokio::main]
@@ -25,11 +66,23 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with_span_events(FmtSpan::CLOSE)
         .init();
 
+    ephemeron::api::load_keys()?;
+    let source = std::env::var("EPHEMERON_CONFIG").unwrap_or_else(|_| "config.yaml".to_owned());
+    let config = get_config(&source).await?;
+    let tls = get_tls(&config)?;
     let client = Client::try_default().await?;
-    let config = get_config()?;
+    let config = SharedConfig::new(config);
+    if is_url(&source) {
+        tokio::spawn(refresh_config(source, refresh_interval()?, config.clone()));
+    }
     let cors = warp::cors()
         .allow_any_origin()
-        .allow_headers(&[header::AUTHORIZATION, header::CONTENT_TYPE])
+        .allow_headers(&[
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            header::IF_MATCH,
+        ])
+        .expose_headers(&[header::ETAG])
         .allow_methods(&[
             Method::DELETE,
             Method::GET,
@@ -37,14 +90,124 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             Method::PATCH,
             Method::POST,
         ]);
+    // Always started, so pools added by refreshing the config are maintained too.
+    tokio::spawn(ephemeron::api::maintain_pool(
+        client.clone(),
+        config.clone(),
+    ));
     let api = ephemeron::api::new(client, config).with(cors);
-    warp::serve(api).run(([0, 0, 0, 0], 3030)).await;
+    let addr = ([0, 0, 0, 0], 3030);
+    if let Some(Tls { cert, key }) = tls {
+        warp::serve(api).tls().cert(cert).key(key).run(addr).await;
+    } else {
+        warp::serve(api).run(addr).await;
+    }
     Ok(())
 }
 
-fn get_config() -> Result<Config, Error> {
-    let config_path =
-        std::env::var("EPHEMERON_CONFIG").unwrap_or_else(|_| "config.yaml".to_owned());
-    let config = std::fs::read(config_path).map_err(Error::ReadConfig)?;
-    serde_yaml::from_slice(&config).map_err(Error::ParseConfig)
+// PEM encoded certificate and private key.
+struct Tls {
+    cert: Vec<u8>,
+    key: Vec<u8>,
+}
+
+impl Tls {
+    // Parse them like `warp` does, because it panics on invalid ones.
+    fn validate(&self) -> Result<(), Error> {
+        use rustls::internal::pemfile;
+
+        let certs = pemfile::certs(&mut self.cert.as_slice())
+            .ok()
+            .filter(|c| !c.is_empty())
+            .ok_or(Error::InvalidTlsCert)?;
+        let key = pemfile::pkcs8_private_keys(&mut self.key.as_slice())
+            .ok()
+            .filter(|k| !k.is_empty())
+            .or_else(|| pemfile::rsa_private_keys(&mut self.key.as_slice()).ok())
+            .and_then(|mut keys| (!keys.is_empty()).then(|| keys.remove(0)))
+            .ok_or(Error::InvalidTlsKey)?;
+        rustls::ServerConfig::new(rustls::NoClientAuth::new())
+            .set_single_cert(certs, key)
+            .map_err(Error::InvalidTls)
+    }
+}
+
+// Read the certificate and the private key if TLS is configured.
+fn get_tls(config: &Config) -> Result<Option<Tls>, Error> {
+    match (&config.tls_cert_path, &config.tls_key_path) {
+        (Some(cert), Some(key)) => {
+            let cert = std::fs::read(cert).map_err(Error::ReadTlsCert)?;
+            let key = std::fs::read(key).map_err(Error::ReadTlsKey)?;
+            let tls = Tls { cert, key };
+            tls.validate()?;
+            Ok(Some(tls))
+        }
+        (None, None) => Ok(None),
+        _ => Err(Error::IncompleteTls),
+    }
+}
+
+// Read the config from a file or an `http(s)://` URL.
+async fn get_config(source: &str) -> Result<Config, Error> {
+    let bytes = if is_url(source) {
+        fetch(source).await?
+    } else {
+        std::fs::read(source).map_err(Error::ReadConfig)?
+    };
+    let config: Config = serde_yaml::from_slice(&bytes).map_err(Error::ParseConfig)?;
+    config.validate().map_err(Error::InvalidConfig)?;
+    Ok(config)
+}
+
+fn is_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+async fn fetch(url: &str) -> Result<Vec<u8>, Error> {
+    tokio::time::timeout(FETCH_TIMEOUT, fetch_body(url))
+        .await
+        .map_err(|_| Error::FetchConfigTimeout)?
+}
+
+async fn fetch_body(url: &str) -> Result<Vec<u8>, Error> {
+    let uri = url.parse::<hyper::Uri>().map_err(Error::InvalidConfigUrl)?;
+    let https = hyper_openssl::HttpsConnector::new().expect("https connector");
+    let client = hyper::Client::builder().build::<_, hyper::Body>(https);
+    let res = client.get(uri).await.map_err(Error::FetchConfig)?;
+    if !res.status().is_success() {
+        return Err(Error::FetchConfigStatus(res.status()));
+    }
+    let body = hyper::body::to_bytes(res.into_body())
+        .await
+        .map_err(Error::FetchConfig)?;
+    Ok(body.to_vec())
+}
+
+fn refresh_interval() -> Result<Duration, Error> {
+    match std::env::var("EPHEMERON_CONFIG_REFRESH_SECONDS") {
+        Ok(v) => v
+            .parse::<u64>()
+            .ok()
+            .filter(|&s| s > 0)
+            .map(Duration::from_secs)
+            .ok_or(Error::InvalidRefreshInterval),
+        Err(_) => Ok(Duration::from_secs(60)),
+    }
+}
+
+// Periodically fetch the config and replace the shared one.
+// Keeps the last good config if fetching fails or the new one is invalid.
+async fn refresh_config(url: String, interval: Duration, config: SharedConfig) {
+    loop {
+        tokio::time::sleep(interval).await;
+        match get_config(&url).await {
+            Ok(new_config) => {
+                tracing::debug!("refreshed config from {}", url);
+                config.replace(new_config);
+            }
+            Err(err) => {
+                tracing::warn!("failed to refresh config, keeping the last one: {}", err);
+            }
+        }
+    }
 }
@@ -1,6 +1,10 @@
 // Start the controller
+use std::{collections::BTreeMap, time::Duration};
+
+use ephemeron::ControllerConfig;
 use kube::Client;
 use tracing_subscriber::fmt::format::FmtSpan;
+use warp::Filter;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
@@ -8,13 +12,100 @@ type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 async fn main() -> Result<()> {
     let domain = std::env::var("EPHEMERON_DOMAIN").expect("EPHEMERON_DOMAIN must be set");
     if domain.is_empty() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "EPHEMERON_DOMAIN must not be empty",
-        )
-        .into());
+        return Err(invalid_data("EPHEMERON_DOMAIN must not be empty"));
+    }
+    let mut config = ControllerConfig::new(domain);
+    if let Ok(ns) = std::env::var("EPHEMERON_NAMESPACE") {
+        if ns.is_empty() {
+            return Err(invalid_data("EPHEMERON_NAMESPACE must not be empty"));
+        }
+        config.namespace = ns;
+    }
+    if let Ok(labels) = std::env::var("EPHEMERON_PROPAGATED_LABELS") {
+        config.propagated_labels = parse_pairs(&labels)
+            .ok_or_else(|| invalid_data("EPHEMERON_PROPAGATED_LABELS must be `label=key,...`"))?;
+    }
+    if let Ok(secrets) = std::env::var("EPHEMERON_IMAGE_PULL_SECRETS") {
+        config.image_pull_secrets = secrets
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(ToOwned::to_owned)
+            .collect();
+    }
+    if let Ok(selector) = std::env::var("EPHEMERON_NODE_SELECTOR") {
+        config.node_selector = parse_pairs(&selector)
+            .ok_or_else(|| invalid_data("EPHEMERON_NODE_SELECTOR must be `label=value,...`"))?;
+    }
+    if let Ok(context) = std::env::var("EPHEMERON_SECURITY_CONTEXT") {
+        config.security_context = Some(serde_json::from_str(&context).map_err(|_| {
+            invalid_data("EPHEMERON_SECURITY_CONTEXT must be a JSON of `securityContext`")
+        })?);
+    }
+    if let Some(interval) = env_seconds("EPHEMERON_RESYNC_INTERVAL_SECONDS")? {
+        config.resync_interval = Some(interval);
+    }
+    if let Some(grace) = env_seconds("EPHEMERON_AVAILABLE_GRACE_PERIOD_SECONDS")? {
+        config.available_grace_period = Some(grace);
+    }
+    if let Some(warning) = env_seconds("EPHEMERON_EXPIRING_WARNING_SECONDS")? {
+        config.expiring_warning = Some(warning);
+    }
+    if let Some(interval) = env_seconds("EPHEMERON_MAX_REQUEUE_INTERVAL_SECONDS")? {
+        config.max_requeue_interval = interval;
+    }
+    if let Some(after) = env_seconds("EPHEMERON_IMAGE_PULL_FALLBACK_AFTER_SECONDS")? {
+        config.image_pull_fallback_after = after;
+    }
+    if let Ok(class) = std::env::var("EPHEMERON_INGRESS_CLASS_NAME") {
+        config.ingress_class_name = Some(class).filter(|c| !c.is_empty());
+    }
+    if let Some(delay) = env_seconds("EPHEMERON_PROBE_INITIAL_DELAY_SECONDS")? {
+        config.probe_retry.initial_delay = delay;
+    }
+    if let Some(delay) = env_seconds("EPHEMERON_PROBE_MAX_DELAY_SECONDS")? {
+        config.probe_retry.max_delay = delay;
+    }
+    if let Ok(n) = std::env::var("EPHEMERON_PROBE_MAX_ATTEMPTS") {
+        config.probe_retry.max_attempts =
+            Some(n.parse::<u32>().ok().filter(|&n| n > 0).ok_or_else(|| {
+                invalid_data("EPHEMERON_PROBE_MAX_ATTEMPTS must be a positive integer")
+            })?);
+    }
+    if let Ok(ns) = std::env::var("EPHEMERON_INGRESS_CONTROLLER_NAMESPACE") {
+        config.ingress_controller_namespace = ns;
+    }
+    if let Ok(labels) = std::env::var("EPHEMERON_INGRESS_CONTROLLER_NAMESPACE_LABELS") {
+        config.ingress_controller_namespace_labels = parse_pairs(&labels).ok_or_else(|| {
+            invalid_data("EPHEMERON_INGRESS_CONTROLLER_NAMESPACE_LABELS must be `label=value,...`")
+        })?;
+    }
+    let metrics_port = match std::env::var("EPHEMERON_METRICS_PORT") {
+        Ok(port) => port
+            .parse::<u16>()
+            .map_err(|_| invalid_data("EPHEMERON_METRICS_PORT must be a port number"))?,
+        Err(_) => 9090,
+    };
+    if let Ok(n) = std::env::var("EPHEMERON_MAX_CONTAINER_RESTARTS") {
+        config.max_container_restarts =
+            n.parse::<i32>().ok().filter(|&n| n >= 0).ok_or_else(|| {
+                invalid_data("EPHEMERON_MAX_CONTAINER_RESTARTS must be a non-negative integer")
+            })?;
     }
 
+    if let Ok(features) = std::env::var("EPHEMERON_FEATURES") {
+        let features = parse_pairs(&features)
+            .ok_or_else(|| invalid_data("EPHEMERON_FEATURES must be `feature=bool,...`"))?;
+        for (name, enabled) in features {
+            let enabled = enabled
+                .parse::<bool>()
+                .map_err(|_| invalid_data("EPHEMERON_FEATURES must be `feature=bool,...`"))?;
+            config.features.set(&name, enabled)?;
+        }
+    }
+
+    config.validate()?;
+
     let filter =
         std::env::var("RUST_LOG").unwrap_or_else(|_| "tracing=info,ephemeron=trace".to_owned());
     tracing_subscriber::fmt()
@@ -23,6 +114,48 @@ async fn main() -> Result<()> {
         .init();
 
     let client = Client::try_default().await?;
-    ephemeron::run(client, domain).await;
+    if config.features.metrics {
+        // GET /metrics
+        let metrics = warp::get()
+            .and(warp::path("metrics"))
+            .and(warp::path::end())
+            .map(ephemeron::metrics::gather);
+        tokio::spawn(warp::serve(metrics).run(([0, 0, 0, 0], metrics_port)));
+    }
+    ephemeron::run(client, config).await;
     Ok(())
 }
+
+// Parse comma separated `key=value` pairs.
+fn parse_pairs(s: &str) -> Option<BTreeMap<String, String>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let (k, v) = p.split_once('=')?;
+            let (k, v) = (k.trim(), v.trim());
+            if k.is_empty() || v.is_empty() {
+                None
+            } else {
+                Some((k.to_owned(), v.to_owned()))
+            }
+        })
+        .collect()
+}
+
+// Read the environment variable as a positive number of seconds.
+fn env_seconds(name: &str) -> Result<Option<Duration>> {
+    match std::env::var(name) {
+        Ok(v) => v
+            .parse::<u64>()
+            .ok()
+            .filter(|&s| s > 0)
+            .map(|s| Some(Duration::from_secs(s)))
+            .ok_or_else(|| invalid_data(&format!("{} must be a positive integer", name))),
+        Err(_) => Ok(None),
+    }
+}
+
+fn invalid_data(message: &str) -> Box<dyn std::error::Error> {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message).into()
+}
@@ -4,7 +4,10 @@ use kube::{
 };
 use thiserror::Error;
 
-use crate::{Ephemeron, EphemeronCondition, EphemeronStatus};
+use crate::{
+    resource::{PHASE_MANAGER, REACHABILITY_MANAGER},
+    Ephemeron, EphemeronCondition, EphemeronStatus,
+};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -14,13 +17,67 @@ pub enum Error {
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 #[tracing::instrument(skip(eph, client), level = "debug")]
-pub async fn set_pod_ready(eph: &Ephemeron, client: Client, status: Option<bool>) -> Result<()> {
-    set_condition(eph, client, EphemeronCondition::pod_ready(status)).await
+pub async fn set_pod_ready(
+    eph: &Ephemeron,
+    client: Client,
+    status: Option<bool>,
+    reason: &str,
+    message: Option<String>,
+) -> Result<()> {
+    set_condition(
+        eph,
+        client,
+        EphemeronCondition::pod_ready(status, reason, message),
+    )
+    .await
 }
 
 #[tracing::instrument(skip(eph, client), level = "debug")]
-pub async fn set_available(eph: &Ephemeron, client: Client, status: Option<bool>) -> Result<()> {
-    set_condition(eph, client, EphemeronCondition::available(status)).await
+pub async fn set_available(
+    eph: &Ephemeron,
+    client: Client,
+    status: Option<bool>,
+    reason: &str,
+    message: Option<String>,
+) -> Result<()> {
+    set_condition(
+        eph,
+        client,
+        EphemeronCondition::available(status, reason, message),
+    )
+    .await
+}
+
+#[tracing::instrument(skip(eph, client), level = "debug")]
+pub async fn set_failed(
+    eph: &Ephemeron,
+    client: Client,
+    status: Option<bool>,
+    reason: &str,
+    message: Option<String>,
+) -> Result<()> {
+    set_condition(
+        eph,
+        client,
+        EphemeronCondition::failed(status, reason, message),
+    )
+    .await
+}
+
+#[tracing::instrument(skip(eph, client), level = "debug")]
+pub async fn set_expiring(
+    eph: &Ephemeron,
+    client: Client,
+    status: Option<bool>,
+    reason: &str,
+    message: Option<String>,
+) -> Result<()> {
+    set_condition(
+        eph,
+        client,
+        EphemeronCondition::expiring(status, reason, message),
+    )
+    .await
 }
 
 async fn set_condition(
@@ -34,15 +91,67 @@ async fn set_condition(
     let ssapply = PatchParams::apply(condition.manager()).force();
     let name = eph.name();
     let api: Api<Ephemeron> = Api::all(client);
+    let updated = api
+        .patch_status(
+            &name,
+            &ssapply,
+            &Patch::Apply(serde_json::json!({
+                "apiVersion": Ephemeron::api_version(&()),
+                "kind": Ephemeron::kind(&()),
+                "status": EphemeronStatus {
+                    conditions: vec![condition],
+                    observed_generation: eph.metadata.generation,
+                    ..EphemeronStatus::default()
+                },
+            })),
+        )
+        .await
+        .map_err(Error::UpdateStatus)?;
+
+    // Summarize the conditions as written, so a step setting more than one doesn't use `eph`
+    // from before the previous ones.
+    let phase = updated.conditions_phase();
+    if updated.phase() != Some(phase) {
+        api.patch_status(
+            &name,
+            &PatchParams::apply(PHASE_MANAGER).force(),
+            &Patch::Apply(serde_json::json!({
+                "apiVersion": Ephemeron::api_version(&()),
+                "kind": Ephemeron::kind(&()),
+                "status": EphemeronStatus {
+                    phase: Some(phase),
+                    ..EphemeronStatus::default()
+                },
+            })),
+        )
+        .await
+        .map_err(Error::UpdateStatus)?;
+    }
+
+    Ok(())
+}
+
+#[tracing::instrument(skip(eph, client), level = "debug")]
+pub async fn set_reachability(
+    eph: &Ephemeron,
+    client: Client,
+    endpoints_ready: bool,
+    externally_reachable: Option<bool>,
+    host: Option<&str>,
+) -> Result<()> {
+    let ssapply = PatchParams::apply(REACHABILITY_MANAGER).force();
+    let api: Api<Ephemeron> = Api::all(client);
     api.patch_status(
-        &name,
+        &eph.name(),
         &ssapply,
         &Patch::Apply(serde_json::json!({
             "apiVersion": Ephemeron::api_version(&()),
             "kind": Ephemeron::kind(&()),
             "status": EphemeronStatus {
-                conditions: vec![condition],
-                observed_generation: eph.metadata.generation,
+                endpoints_ready: Some(endpoints_ready),
+                externally_reachable,
+                host: host.map(ToOwned::to_owned),
+                ..EphemeronStatus::default()
             },
         })),
     )
@@ -1,15 +1,16 @@
-use std::time::Duration;
+use std::{net::IpAddr, time::Duration};
 
-use k8s_openapi::api::core::v1::Endpoints;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::{Endpoints, Service};
 use kube::{
     api::{Patch, PatchParams},
     runtime::controller::{Action, Context},
-    Api, ResourceExt,
+    Api, Client, ResourceExt,
 };
 use thiserror::Error;
 
-use super::{conditions, ContextData};
-use crate::Ephemeron;
+use super::{conditions, ContextData, HttpClient, ProbeRetry};
+use crate::{resource::HttpGetProbe, Ephemeron};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -19,20 +20,47 @@ pub enum Error {
     #[error("failed to annotate host information: {0}")]
     HostAnnotation(#[source] kube::Error),
 
+    #[error("failed to get service: {0}")]
+    GetService(#[source] kube::Error),
+
+    #[error("failed to annotate: {0}")]
+    Annotate(#[source] kube::Error),
+
     #[error("failed to update condition: {0}")]
     UpdateCondition(#[source] conditions::Error),
 }
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+// Annotation to record when the endpoints became not ready while `Available`.
+const UNREADY_SINCE: &str = "ephemerons.qualified.io/unready-since";
+// Annotation to count the failures of the probes from outside in a row.
+const PROBE_ATTEMPTS: &str = "ephemerons.qualified.io/probe-attempts";
+// Annotation to record when to probe again after a failure.
+// Patching the annotations triggers a reconciliation, so the backoff is kept here instead of in the requeue.
+const NEXT_PROBE_AFTER: &str = "ephemerons.qualified.io/next-probe-after";
+// The reason of `Available` after giving up the probes.
+const PROBE_RETRIES_EXHAUSTED: &str = "ProbeRetriesExhausted";
+// Annotation to record when `callbackUrl` was called, so that it's called at most once.
+const CALLBACK_SENT: &str = "ephemerons.qualified.io/callback-sent";
+// Slow receivers shouldn't keep the requests around.
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[tracing::instrument(skip(eph, ctx), level = "trace")]
 pub(super) async fn reconcile(
     eph: &Ephemeron,
     ctx: Context<ContextData>,
 ) -> Result<Option<Action>> {
+    // No endpoints while paused. The conditions are set by the Pod step.
+    if eph.spec.paused {
+        return Ok(None);
+    }
     let name = eph.name();
     let client = ctx.get_ref().client.clone();
     // Check if service has endpoints
-    let endpoints: Api<Endpoints> = Api::namespaced(client.clone(), super::NS);
+    let endpoints: Api<Endpoints> = Api::namespaced(
+        client.clone(),
+        eph.namespace_or(&ctx.get_ref().config.namespace),
+    );
     if let Some(Endpoints { subsets, .. }) = endpoints
         .get_opt(&name)
         .await
@@ -42,64 +70,152 @@ pub(super) async fn reconcile(
             ess.iter()
                 .any(|es| es.addresses.as_ref().map_or(false, |a| !a.is_empty()))
         });
+        // Only the endpoints are checked if the probing is disabled.
+        let probing = ctx.get_ref().config.features.probe_external_availability;
+        let has_probes = probing && eph.readiness_probes().next().is_some();
+        // Keep `Available` during the grace period so that it doesn't flap while the Pod is replaced.
+        if let (true, false, Some(grace)) = (
+            eph.is_available(),
+            has_ready,
+            ctx.get_ref().config.available_grace_period,
+        ) {
+            if let Some(wait) = wait_for_grace(eph, client.clone(), grace).await? {
+                tracing::debug!("no ready endpoints, keeping available for {:?}", wait);
+                return Ok(Some(Action::requeue(wait)));
+            }
+        }
+
         match (eph.is_available(), has_ready) {
             // Nothing to do if it's ready and the condition agrees.
-            (true, true) => Ok(None),
+            (true, true) => {
+                if eph.annotations().contains_key(UNREADY_SINCE) {
+                    annotate(eph, client.clone(), UNREADY_SINCE, None).await?;
+                }
+                send_callback(eph, client.clone(), &ctx.get_ref().http_client).await?;
+                let host = eph.annotations().get("host").map(String::as_str);
+                update_reachability(eph, client, true, eph.externally_reachable(), host).await?;
+                Ok(None)
+            }
             // Requeue soon if `Endpoints` exists, but not ready yet.
-            (false, false) => Ok(Some(Action::requeue(Duration::from_secs(1)))),
+            (false, false) => {
+                // Retry the probes from the start when it becomes ready again.
+                if eph.annotations().contains_key(PROBE_ATTEMPTS) {
+                    annotate_probe_attempts(eph, client.clone(), None).await?;
+                }
+                update_reachability(eph, client, false, has_probes.then(|| false), None).await?;
+                Ok(Some(Action::requeue(Duration::from_secs(1))))
+            }
             // Fix outdated condition
             (_, available) => {
-                let host = if available {
+                let retry = &ctx.get_ref().config.probe_retry;
+                let mut attempts = probe_attempts(eph);
+                if available && has_given_up(eph, retry) {
+                    return Ok(None);
+                }
+                // Wait for the backoff even if reconciled earlier by the changes.
+                if available {
+                    let next = eph.annotations().get(NEXT_PROBE_AFTER);
+                    if let Some(wait) = wait_for_next_probe(next.map(String::as_str), Utc::now()) {
+                        return Ok(Some(Action::requeue(wait)));
+                    }
+                }
+                let (host, reason, message) = if available {
                     // HACK Make sure the service is available from outside.
                     // The address is marked as ready to be utilized, but that doesn't mean it's usable from outside.
-                    let domain: &str = ctx.get_ref().domain.as_ref();
-                    let host = format!("{}.{}", &name, domain);
-                    if let Some(probe) = eph.spec.service.readiness_probe.as_ref() {
-                        let uri = hyper::Uri::builder()
-                            .scheme(if eph.has_tls() { "https" } else { "http" })
-                            .authority(host.clone())
-                            .path_and_query(probe.path.clone())
-                            .build()
-                            .expect("valid uri from host");
-                        tracing::debug!("testing if {} is available", uri);
-                        let http_client = ctx.get_ref().http_client.clone();
-                        match http_client.get(uri).await {
-                            Ok(res) if res.status() == hyper::StatusCode::OK => {
-                                tracing::debug!("the service is available");
-                                Some(host)
-                            }
-                            Ok(res) => {
-                                tracing::debug!(
-                                    "the service is not available yet {}",
-                                    res.status()
-                                );
-                                // Try again after 1s, or the next cycle.
-                                return Ok(Some(Action::requeue(Duration::from_secs(1))));
+                    let host = if eph.is_load_balancer() {
+                        let config = &ctx.get_ref().config;
+                        match load_balancer_host(
+                            eph,
+                            client.clone(),
+                            eph.namespace_or(&config.namespace),
+                        )
+                        .await?
+                        {
+                            Some(host) => host,
+                            // Wait for the address to be assigned.
+                            None => return Ok(Some(Action::requeue(Duration::from_secs(2)))),
+                        }
+                    } else {
+                        let domain: &str = ctx.get_ref().config.domain.as_ref();
+                        format!("{}.{}", &name, domain)
+                    };
+                    // All of the probes must succeed.
+                    let http_client = &ctx.get_ref().http_client;
+                    let mut failure = None;
+                    for probe in eph.readiness_probes().filter(|_| probing) {
+                        match check_probe(http_client, eph, &host, probe).await {
+                            Ok(true) => {}
+                            // Try again with backoff until giving up.
+                            Ok(false) => {
+                                attempts += 1;
+                                let delay = match retry_after(retry, attempts, &probe.path) {
+                                    Ok(delay) => delay,
+                                    Err(given_up) => {
+                                        tracing::debug!("giving up after {} attempts", attempts);
+                                        failure = Some(given_up);
+                                        break;
+                                    }
+                                };
+                                let next = chrono::Duration::from_std(delay)
+                                    .ok()
+                                    .and_then(|d| Utc::now().checked_add_signed(d));
+                                annotate_probe_attempts(
+                                    eph,
+                                    client.clone(),
+                                    next.map(|next| (attempts, next)),
+                                )
+                                .await?;
+                                update_reachability(eph, client, true, Some(false), None).await?;
+                                return Ok(Some(Action::requeue(delay)));
                             }
                             Err(err) => {
                                 tracing::warn!("failed to check availability {}", err);
-                                None
+                                failure = Some((
+                                    "ProbeFailed",
+                                    format!("failed to check {}: {}", probe.path, err),
+                                ));
+                                break;
                             }
                         }
+                    }
+                    if let Some((reason, message)) = failure {
+                        (None, reason, Some(message))
                     } else {
-                        Some(host)
+                        tracing::debug!("the service is available");
+                        (Some(host), "ServiceAvailable", None)
                     }
                 } else {
-                    None
+                    (None, "NoReadyEndpoints", None)
                 };
 
+                // Kept after giving up to stop probing.
+                let attempts = (reason == PROBE_RETRIES_EXHAUSTED).then(|| attempts.to_string());
                 let api: Api<Ephemeron> = Api::all(client.clone());
                 api.patch(
                     &name,
                     &PatchParams::default(),
                     &Patch::Merge(serde_json::json!({
-                        "metadata": { "annotations": { "host": host } },
+                        "metadata": { "annotations": {
+                            "host": host,
+                            UNREADY_SINCE: null,
+                            PROBE_ATTEMPTS: attempts,
+                            NEXT_PROBE_AFTER: null,
+                        } },
                     })),
                 )
                 .await
                 .map_err(Error::HostAnnotation)?;
 
-                conditions::set_available(eph, client, Some(host.is_some()))
+                let externally_reachable = has_probes.then(|| host.is_some());
+                update_reachability(
+                    eph,
+                    client.clone(),
+                    available,
+                    externally_reachable,
+                    host.as_deref(),
+                )
+                .await?;
+                conditions::set_available(eph, client, Some(host.is_some()), reason, message)
                     .await
                     .map_err(Error::UpdateCondition)?;
 
@@ -110,3 +226,476 @@ pub(super) async fn reconcile(
         Ok(Some(Action::requeue(Duration::from_secs(2))))
     }
 }
+
+// `true` if gave up probing until the endpoints become not ready.
+fn has_given_up(eph: &Ephemeron, retry: &ProbeRetry) -> bool {
+    retry.exhausted(probe_attempts(eph)) && eph.available_reason() == Some(PROBE_RETRIES_EXHAUSTED)
+}
+
+// The delay to retry after the probe at `path` failed `attempts` times in a row.
+// The reason and the message to mark not `Available` with if giving up.
+fn retry_after(
+    retry: &ProbeRetry,
+    attempts: u32,
+    path: &str,
+) -> Result<Duration, (&'static str, String)> {
+    if retry.exhausted(attempts) {
+        Err((
+            PROBE_RETRIES_EXHAUSTED,
+            format!("{} failed {} times", path, attempts),
+        ))
+    } else {
+        Ok(retry.delay(attempts))
+    }
+}
+
+// The URL and the body of the callback to send. `None` if already sent, or not available yet.
+fn callback(eph: &Ephemeron) -> Option<(&str, serde_json::Value)> {
+    if eph.annotations().contains_key(CALLBACK_SENT) {
+        return None;
+    }
+    let url = eph.spec.callback_url.as_deref()?;
+    let host = eph.annotations().get("host")?;
+    Some((
+        url,
+        serde_json::json!({
+            "id": eph.name(),
+            "host": host,
+            "tls": eph.has_tls(),
+            "expirationTime": eph.spec.expiration_time,
+        }),
+    ))
+}
+
+// The number of failures of the probes in a row.
+fn probe_attempts(eph: &Ephemeron) -> u32 {
+    eph.annotations()
+        .get(PROBE_ATTEMPTS)
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0)
+}
+
+// Returns how long to wait before marking unavailable if it's within the grace period
+// since no endpoints were ready.
+async fn wait_for_grace(
+    eph: &Ephemeron,
+    client: Client,
+    grace: Duration,
+) -> Result<Option<Duration>> {
+    let since = eph
+        .annotations()
+        .get(UNREADY_SINCE)
+        .and_then(|v| DateTime::parse_from_rfc3339(v).ok());
+    if let Some(since) = since {
+        let elapsed = (Utc::now() - since.with_timezone(&Utc))
+            .to_std()
+            .unwrap_or_default();
+        Ok(grace.checked_sub(elapsed).filter(|d| !d.is_zero()))
+    } else {
+        annotate(eph, client, UNREADY_SINCE, Some(Utc::now().to_rfc3339())).await?;
+        Ok(Some(grace))
+    }
+}
+
+// `POST` `{id, host, tls, expirationTime}` to `callbackUrl` if it's not called yet.
+// Annotated before sending, so it's not retried. The request is sent in the background,
+// and the failures are only logged.
+async fn send_callback(eph: &Ephemeron, client: Client, http_client: &HttpClient) -> Result<()> {
+    let (url, body) = match callback(eph) {
+        Some(callback) => callback,
+        None => return Ok(()),
+    };
+    annotate(eph, client, CALLBACK_SENT, Some(Utc::now().to_rfc3339())).await?;
+
+    let req = match hyper::Request::post(url)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(hyper::Body::from(body.to_string()))
+    {
+        Ok(req) => req,
+        Err(err) => {
+            tracing::warn!("invalid callback url {}: {}", url, err);
+            return Ok(());
+        }
+    };
+    let http_client = http_client.clone();
+    let name = eph.name();
+    tokio::spawn(async move {
+        // The API only accepts the allowed hosts, but they shouldn't resolve to the cluster either.
+        if !resolves_to_public(req.uri()).await {
+            tracing::warn!("callback for {} is not sent to non-public address", name);
+            return;
+        }
+        match tokio::time::timeout(CALLBACK_TIMEOUT, http_client.request(req)).await {
+            Ok(Ok(res)) if res.status().is_success() => {
+                tracing::debug!("sent callback for {}", name);
+            }
+            Ok(Ok(res)) => tracing::warn!("callback for {} responded {}", name, res.status()),
+            Ok(Err(err)) => tracing::warn!("failed to send callback for {}: {}", name, err),
+            Err(_) => tracing::warn!("callback for {} timed out", name),
+        }
+    });
+    Ok(())
+}
+
+// `true` if all the addresses of the host of `uri` are public.
+async fn resolves_to_public(uri: &hyper::Uri) -> bool {
+    let host = match uri.host() {
+        Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+        None => return false,
+    };
+    let port = uri
+        .port_u16()
+        .unwrap_or(if uri.scheme_str() == Some("https") {
+            443
+        } else {
+            80
+        });
+    match tokio::time::timeout(CALLBACK_TIMEOUT, tokio::net::lookup_host((host, port))).await {
+        Ok(Ok(addrs)) => {
+            let ips: Vec<IpAddr> = addrs.map(|a| a.ip()).collect();
+            !ips.is_empty() && ips.into_iter().all(is_public)
+        }
+        _ => false,
+    }
+}
+
+// Loopback, private, link-local (e.g., the metadata server), and other special addresses are not public.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                // Shared address space (100.64.0.0/10).
+                || (a == 100 && (b & 0xc0) == 64))
+        }
+        IpAddr::V6(ip) => match ip.segments() {
+            // IPv4-mapped (::ffff:0:0/96).
+            [0, 0, 0, 0, 0, 0xffff, ..] => {
+                is_public(IpAddr::V4(ip.to_ipv4().expect("IPv4-mapped address")))
+            }
+            [first, ..] => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    // Unique local (fc00::/7) and link-local (fe80::/10).
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+// Record the failures and when to probe again, or remove them if `None`.
+async fn annotate_probe_attempts(
+    eph: &Ephemeron,
+    client: Client,
+    failed: Option<(u32, DateTime<Utc>)>,
+) -> Result<()> {
+    let (attempts, next) = failed.map_or((None, None), |(attempts, next)| {
+        (Some(attempts.to_string()), Some(next.to_rfc3339()))
+    });
+    let api: Api<Ephemeron> = Api::all(client);
+    api.patch(
+        &eph.name(),
+        &PatchParams::default(),
+        &Patch::Merge(serde_json::json!({
+            "metadata": { "annotations": {
+                PROBE_ATTEMPTS: attempts,
+                NEXT_PROBE_AFTER: next,
+            } },
+        })),
+    )
+    .await
+    .map_err(Error::Annotate)?;
+    Ok(())
+}
+
+// How long to wait until `next` (`NEXT_PROBE_AFTER`). `None` if it's time to probe.
+fn wait_for_next_probe(next: Option<&str>, now: DateTime<Utc>) -> Option<Duration> {
+    let next = DateTime::parse_from_rfc3339(next?).ok()?;
+    (next.with_timezone(&Utc) - now)
+        .to_std()
+        .ok()
+        .filter(|d| !d.is_zero())
+}
+
+// Set or remove the annotation.
+async fn annotate(eph: &Ephemeron, client: Client, key: &str, value: Option<String>) -> Result<()> {
+    let api: Api<Ephemeron> = Api::all(client);
+    api.patch(
+        &eph.name(),
+        &PatchParams::default(),
+        &Patch::Merge(serde_json::json!({
+            "metadata": { "annotations": { key: value } },
+        })),
+    )
+    .await
+    .map_err(Error::Annotate)?;
+    Ok(())
+}
+
+// Update `endpointsReady`, `externallyReachable`, and `host` in status if changed.
+async fn update_reachability(
+    eph: &Ephemeron,
+    client: Client,
+    endpoints_ready: bool,
+    externally_reachable: Option<bool>,
+    host: Option<&str>,
+) -> Result<()> {
+    if eph.endpoints_ready() == Some(endpoints_ready)
+        && eph.externally_reachable() == externally_reachable
+        && eph.status_host() == host
+    {
+        return Ok(());
+    }
+    conditions::set_reachability(eph, client, endpoints_ready, externally_reachable, host)
+        .await
+        .map_err(Error::UpdateCondition)
+}
+
+// The address assigned to the load balancer with the port. `None` if not assigned yet.
+async fn load_balancer_host(eph: &Ephemeron, client: Client, ns: &str) -> Result<Option<String>> {
+    let svcs: Api<Service> = Api::namespaced(client, ns);
+    let svc = svcs.get_opt(&eph.name()).await.map_err(Error::GetService)?;
+    Ok(svc.and_then(|svc| load_balancer_address(&svc, eph.spec.service.port)))
+}
+
+// The first address in the status of the load balancer with `port`. Hostnames are preferred over IPs.
+fn load_balancer_address(svc: &Service, port: i32) -> Option<String> {
+    let non_empty = |a: &Option<String>| a.clone().filter(|a| !a.is_empty());
+    svc.status
+        .as_ref()
+        .and_then(|status| status.load_balancer.as_ref())
+        .and_then(|lb| lb.ingress.as_ref())
+        .and_then(|ingress| {
+            ingress
+                .iter()
+                .find_map(|i| non_empty(&i.hostname).or_else(|| non_empty(&i.ip)))
+        })
+        .map(|address| format!("{}:{}", address, port))
+}
+
+// The URI to check the `probe` from outside. Without the port, so the default of the scheme is used.
+fn probe_uri(eph: &Ephemeron, host: &str, probe: &HttpGetProbe) -> hyper::Uri {
+    let https = match probe.scheme.as_deref() {
+        Some(scheme) => scheme.eq_ignore_ascii_case("https"),
+        None => eph.has_tls(),
+    };
+    hyper::Uri::builder()
+        .scheme(if https { "https" } else { "http" })
+        .authority(host)
+        .path_and_query(probe.path.clone())
+        .build()
+        .expect("valid uri from host")
+}
+
+// Returns `true` if the `probe` responded with OK from outside.
+async fn check_probe(
+    http_client: &HttpClient,
+    eph: &Ephemeron,
+    host: &str,
+    probe: &HttpGetProbe,
+) -> Result<bool, hyper::Error> {
+    let uri = probe_uri(eph, host, probe);
+    tracing::debug!("testing if {} is available", uri);
+    let res = http_client.get(uri).await?;
+    if res.status().is_success() {
+        Ok(true)
+    } else {
+        tracing::debug!("the service is not available yet {}", res.status());
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn retry() -> ProbeRetry {
+        ProbeRetry {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(10),
+            max_attempts: Some(3),
+        }
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let retry = retry();
+        assert_eq!(retry_after(&retry, 1, "/"), Ok(Duration::from_secs(1)));
+        assert_eq!(retry_after(&retry, 2, "/"), Ok(Duration::from_secs(2)));
+        assert_eq!(
+            retry_after(&retry, 3, "/health"),
+            Err((PROBE_RETRIES_EXHAUSTED, "/health failed 3 times".to_owned()))
+        );
+        let unlimited = ProbeRetry::default();
+        assert_eq!(retry_after(&unlimited, 100, "/"), Ok(unlimited.max_delay));
+    }
+
+    #[test]
+    fn test_callback() {
+        let mut eph =
+            crate::resource::test_ephemeron(serde_json::json!({"image": "nginx", "port": 80}));
+        eph.spec.callback_url = Some("https://hooks.example.com/ready".to_owned());
+        // Not available yet.
+        assert_eq!(callback(&eph), None);
+        eph.annotations_mut()
+            .insert("host".to_owned(), "test.example.com".to_owned());
+        assert_eq!(
+            callback(&eph),
+            Some((
+                "https://hooks.example.com/ready",
+                serde_json::json!({
+                    "id": "test",
+                    "host": "test.example.com",
+                    "tls": false,
+                    "expirationTime": "2030-01-01T00:00:00Z",
+                })
+            ))
+        );
+        // Only once.
+        eph.annotations_mut()
+            .insert(CALLBACK_SENT.to_owned(), Utc::now().to_rfc3339());
+        assert_eq!(callback(&eph), None);
+        // Not requested.
+        eph.annotations_mut().remove(CALLBACK_SENT);
+        eph.spec.callback_url = None;
+        assert_eq!(callback(&eph), None);
+    }
+
+    #[test]
+    fn test_has_given_up() {
+        let retry = retry();
+        let mut eph =
+            crate::resource::test_ephemeron(serde_json::json!({"image": "nginx", "port": 80}));
+        assert!(!has_given_up(&eph, &retry));
+        eph.annotations_mut()
+            .insert(PROBE_ATTEMPTS.to_owned(), "3".to_owned());
+        // Not marked yet.
+        assert!(!has_given_up(&eph, &retry));
+        eph.status = Some(crate::EphemeronStatus {
+            conditions: vec![crate::EphemeronCondition::available(
+                Some(false),
+                PROBE_RETRIES_EXHAUSTED,
+                None,
+            )],
+            ..crate::EphemeronStatus::default()
+        });
+        assert!(has_given_up(&eph, &retry));
+        // Retries from the start once the attempts are cleared.
+        eph.annotations_mut().remove(PROBE_ATTEMPTS);
+        assert!(!has_given_up(&eph, &retry));
+    }
+
+    #[test]
+    fn test_probe_uri() {
+        let probe = |scheme: Option<&str>| -> HttpGetProbe {
+            serde_json::from_value(serde_json::json!({"path": "/health?full=1", "scheme": scheme}))
+                .unwrap()
+        };
+        let plain =
+            crate::resource::test_ephemeron(serde_json::json!({"image": "nginx", "port": 80}));
+        let tls = crate::resource::test_ephemeron(serde_json::json!({
+            "image": "nginx",
+            "port": 80,
+            "tlsSecretName": "tls",
+        }));
+        let host = "test.example.com";
+        assert_eq!(
+            probe_uri(&plain, host, &probe(None)).to_string(),
+            "http://test.example.com/health?full=1"
+        );
+        assert_eq!(
+            probe_uri(&tls, host, &probe(None)).to_string(),
+            "https://test.example.com/health?full=1"
+        );
+        // `scheme` overrides the default.
+        assert_eq!(
+            probe_uri(&plain, host, &probe(Some("HTTPS"))).scheme_str(),
+            Some("https")
+        );
+        assert_eq!(
+            probe_uri(&tls, host, &probe(Some("HTTP"))).scheme_str(),
+            Some("http")
+        );
+        // Load balancers are accessed with the port.
+        let uri = probe_uri(&plain, "203.0.113.1:8080", &probe(None));
+        assert_eq!(
+            uri.authority().map(hyper::http::uri::Authority::as_str),
+            Some("203.0.113.1:8080")
+        );
+    }
+
+    #[allow(clippy::needless_pass_by_value)]
+    fn load_balancer(ingress: serde_json::Value) -> Service {
+        serde_json::from_value(serde_json::json!({
+            "metadata": { "name": "test" },
+            "status": { "loadBalancer": { "ingress": ingress } },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_load_balancer_address() {
+        let address = |ingress| load_balancer_address(&load_balancer(ingress), 8080);
+        assert_eq!(address(serde_json::json!([])), None);
+        assert_eq!(
+            address(serde_json::json!([{"ip": "203.0.113.1"}])).as_deref(),
+            Some("203.0.113.1:8080")
+        );
+        assert_eq!(
+            address(serde_json::json!([{"hostname": "lb.example.com", "ip": "203.0.113.1"}]))
+                .as_deref(),
+            Some("lb.example.com:8080")
+        );
+        // Empty ones are skipped.
+        assert_eq!(
+            address(serde_json::json!([{"hostname": "", "ip": ""}, {"ip": "203.0.113.2"}]))
+                .as_deref(),
+            Some("203.0.113.2:8080")
+        );
+        assert_eq!(
+            address(serde_json::json!([{"hostname": "", "ip": "203.0.113.3"}])).as_deref(),
+            Some("203.0.113.3:8080")
+        );
+        assert_eq!(load_balancer_address(&Service::default(), 80), None);
+    }
+
+    #[test]
+    fn test_wait_for_next_probe() {
+        let now = Utc::now();
+        let later = (now + chrono::Duration::seconds(5)).to_rfc3339();
+        let earlier = (now - chrono::Duration::seconds(5)).to_rfc3339();
+        let wait = wait_for_next_probe(Some(&later), now).unwrap();
+        assert!(wait > Duration::from_secs(4) && wait <= Duration::from_secs(5));
+        assert_eq!(wait_for_next_probe(Some(&earlier), now), None);
+        assert_eq!(wait_for_next_probe(None, now), None);
+        assert_eq!(wait_for_next_probe(Some("invalid"), now), None);
+    }
+
+    #[test]
+    fn test_is_public() {
+        for ip in ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:10.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+}
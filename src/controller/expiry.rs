@@ -6,36 +6,40 @@ use kube::{
 };
 use thiserror::Error;
 
-use super::ContextData;
+use super::{conditions, ContextData};
 use crate::Ephemeron;
 
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("failed to delete ephemeron: {0}")]
     Delete(#[source] kube::Error),
+
+    #[error("failed to update condition: {0}")]
+    UpdateCondition(#[source] conditions::Error),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
-/// Delete the resource if it's expired.
+/// Delete the resource if it's expired. Mark `Expiring` within the warning window before that.
 #[tracing::instrument(skip(eph, ctx), level = "trace")]
 pub(super) async fn reconcile(
     eph: &Ephemeron,
     ctx: Context<ContextData>,
 ) -> Result<Option<Action>> {
     if eph.spec.expiration_time > Utc::now() {
-        return Ok(None);
+        return update_expiring(eph, ctx).await;
     }
 
     tracing::debug!("Resource expired, deleting");
     let name = eph.name();
-    // Delete the owner with `propagationPolicy=Background`.
-    // This will delete the owner immediately, then children are deleted by garbage collector.
+    // Delete the owner with `propagationPolicy=Foreground`.
+    // The owner is kept with `deletionTimestamp` until the garbage collector deletes the children
+    // blocking owner deletion. The name can't be reused while the old `Ingress` may still serve the host.
     let api: Api<Ephemeron> = Api::all(ctx.get_ref().client.clone());
     api.delete(
         &name,
         &DeleteParams {
-            propagation_policy: Some(PropagationPolicy::Background),
+            propagation_policy: Some(PropagationPolicy::Foreground),
             ..DeleteParams::default()
         },
     )
@@ -44,3 +48,83 @@ pub(super) async fn reconcile(
 
     Ok(Some(Action::await_change()))
 }
+
+// Keep `Expiring` in sync with the window. The lifetime can be extended after it's set.
+async fn update_expiring(eph: &Ephemeron, ctx: Context<ContextData>) -> Result<Option<Action>> {
+    let warning = match ctx.get_ref().config.expiring_warning {
+        Some(warning) => warning,
+        None => return Ok(None),
+    };
+    let expiring = match expiring_update(eph, warning, Utc::now()) {
+        Some(expiring) => expiring,
+        None => return Ok(None),
+    };
+
+    let client = ctx.get_ref().client.clone();
+    if expiring {
+        tracing::debug!("Resource is expiring");
+        let message = format!("expires at {}", eph.spec.expiration_time.to_rfc3339());
+        conditions::set_expiring(eph, client, Some(true), "ExpiringSoon", Some(message))
+            .await
+            .map_err(Error::UpdateCondition)?;
+    } else {
+        conditions::set_expiring(eph, client, Some(false), "LifetimeExtended", None)
+            .await
+            .map_err(Error::UpdateCondition)?;
+    }
+    Ok(Some(Action::await_change()))
+}
+
+// The new status of `Expiring` at `now`. `None` if it's up to date.
+fn expiring_update(
+    eph: &Ephemeron,
+    warning: std::time::Duration,
+    now: chrono::DateTime<Utc>,
+) -> Option<bool> {
+    let expiring = super::warning_time(eph, warning) <= now;
+    (expiring != eph.is_expiring()).then(|| expiring)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::{EphemeronCondition, EphemeronStatus};
+
+    #[test]
+    fn test_expiring_update() {
+        let warning = std::time::Duration::from_secs(600);
+        let mut eph =
+            crate::resource::test_ephemeron(serde_json::json!({"image": "nginx", "port": 80}));
+        let expiration_time = eph.spec.expiration_time;
+        let minutes = chrono::Duration::minutes;
+
+        // Not in the window yet.
+        assert_eq!(
+            expiring_update(&eph, warning, expiration_time - minutes(11)),
+            None
+        );
+        // Entered the window.
+        assert_eq!(
+            expiring_update(&eph, warning, expiration_time - minutes(10)),
+            Some(true)
+        );
+        eph.status = Some(EphemeronStatus {
+            conditions: vec![EphemeronCondition::expiring(
+                Some(true),
+                "ExpiringSoon",
+                None,
+            )],
+            ..EphemeronStatus::default()
+        });
+        assert_eq!(
+            expiring_update(&eph, warning, expiration_time - minutes(5)),
+            None
+        );
+        // Extended out of the window.
+        eph.spec.expiration_time = expiration_time + minutes(60);
+        assert_eq!(
+            expiring_update(&eph, warning, expiration_time - minutes(5)),
+            Some(false)
+        );
+    }
+}
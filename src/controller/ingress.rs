@@ -3,14 +3,14 @@ use k8s_openapi::api::networking::v1::{
     IngressServiceBackend, IngressSpec, IngressTLS, ServiceBackendPort,
 };
 use kube::{
-    api::{ObjectMeta, PostParams},
+    api::{DeleteParams, ObjectMeta, Patch, PatchParams, PostParams},
     error::ErrorResponse,
     runtime::controller::{Action, Context},
     Api, ResourceExt,
 };
 use thiserror::Error;
 
-use super::ContextData;
+use super::{Config, ContextData};
 use crate::Ephemeron;
 
 #[derive(Debug, Error)]
@@ -20,6 +20,12 @@ pub enum Error {
 
     #[error("failed to get ingress: {0}")]
     GetIngress(#[source] kube::Error),
+
+    #[error("failed to delete ingress: {0}")]
+    DeleteIngress(#[source] kube::Error),
+
+    #[error("failed to correct ingress: {0}")]
+    CorrectDrift(#[source] kube::Error),
 }
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
@@ -28,20 +34,51 @@ pub(super) async fn reconcile(
     eph: &Ephemeron,
     ctx: Context<ContextData>,
 ) -> Result<Option<Action>> {
+    if !ctx.get_ref().config.features.create_ingress {
+        return Ok(None);
+    }
     let name = eph.name();
     let client = ctx.get_ref().client.clone();
 
-    let ings: Api<Ingress> = Api::namespaced(client.clone(), super::NS);
-    if ings
-        .get_opt(&name)
+    let ings: Api<Ingress> = Api::namespaced(
+        client.clone(),
+        eph.namespace_or(&ctx.get_ref().config.namespace),
+    );
+    // Only HTTP can be routed through the Ingress.
+    // Delete the one created before the protocol changed, so the host doesn't route to the port anymore.
+    if !eph.is_http_routable() {
+        if ings
+            .get_opt(&name)
+            .await
+            .map_err(Error::GetIngress)?
+            .is_none()
+        {
+            return Ok(None);
+        }
+        tracing::debug!("Deleting Ingress for {}", eph.protocol());
+        return match ings.delete(&name, &DeleteParams::background()).await {
+            Ok(_) | Err(kube::Error::Api(ErrorResponse { code: 404, .. })) => Ok(None),
+
+            Err(err) => Err(Error::DeleteIngress(err)),
+        };
+    }
+    let ing = build_ingress(eph, &ctx.get_ref().config);
+    if let Some(current) = ings.get_opt(&name).await.map_err(Error::GetIngress)? {
+        if !has_drifted(&current, &ing) {
+            return Ok(None);
+        }
+        // Take back the fields changed by others.
+        tracing::debug!("Correcting Ingress");
+        ings.patch(
+            &name,
+            &PatchParams::apply(super::FIELD_MANAGER).force(),
+            &Patch::Apply(&ing),
+        )
         .await
-        .map_err(Error::GetIngress)?
-        .is_some()
-    {
-        Ok(None)
+        .map_err(Error::CorrectDrift)?;
+        Ok(Some(Action::await_change()))
     } else {
         tracing::debug!("Creating Ingress");
-        let ing = build_ingress(eph, ctx.get_ref().domain.as_ref());
         match ings.create(&PostParams::default(), &ing).await {
             Ok(_) => Ok(Some(Action::await_change())),
 
@@ -55,7 +92,19 @@ pub(super) async fn reconcile(
     }
 }
 
-fn build_ingress(eph: &Ephemeron, domain: &str) -> Ingress {
+// `true` if the managed fields of `current` differ from `desired`.
+// The class is only compared when set, because the server can default it.
+fn has_drifted(current: &Ingress, desired: &Ingress) -> bool {
+    let managed = |ing: &Ingress| {
+        let spec = ing.spec.clone().unwrap_or_default();
+        (spec.rules, spec.tls.unwrap_or_default())
+    };
+    let class = |ing: &Ingress| ing.spec.as_ref().and_then(|s| s.ingress_class_name.clone());
+    managed(current) != managed(desired)
+        || (class(desired).is_some() && class(current) != class(desired))
+}
+
+fn build_ingress(eph: &Ephemeron, config: &Config) -> Ingress {
     let name = eph.name();
     let tls = eph.spec.service.tls_secret_name.clone().map(|name| {
         vec![IngressTLS {
@@ -63,34 +112,51 @@ fn build_ingress(eph: &Ephemeron, domain: &str) -> Ingress {
             secret_name: Some(name),
         }]
     });
+    let mut labels = eph.spec.service.ingress_labels.clone();
+    labels.append(&mut super::make_labels(eph, config));
     Ingress {
         metadata: ObjectMeta {
             name: Some(name.clone()),
-            namespace: Some(super::NS.into()),
-            labels: Some(super::make_common_labels(&name)),
+            namespace: Some(eph.namespace_or(&config.namespace).to_owned()),
+            labels: Some(labels),
             owner_references: Some(vec![super::to_owner_reference(eph)]),
             annotations: Some(eph.spec.service.ingress_annotations.clone()),
             ..ObjectMeta::default()
         },
         spec: Some(IngressSpec {
+            ingress_class_name: eph
+                .spec
+                .service
+                .ingress_class_name
+                .clone()
+                .or_else(|| config.ingress_class_name.clone()),
             tls: Some(tls.unwrap_or_default()),
             rules: Some(vec![IngressRule {
-                host: Some(format!("{}.{}", name, domain)),
+                host: Some(format!("{}.{}", name, config.domain)),
                 http: Some(HTTPIngressRuleValue {
-                    paths: vec![HTTPIngressPath {
-                        path: Some("/".into()),
-                        path_type: Some("Prefix".into()),
-                        backend: IngressBackend {
-                            service: Some(IngressServiceBackend {
-                                name: name.clone(),
-                                port: Some(ServiceBackendPort {
-                                    number: Some(eph.spec.service.port),
-                                    name: None,
+                    paths: eph
+                        .spec
+                        .service
+                        .additional_ports
+                        .iter()
+                        .filter(|p| p.protocol() == "TCP")
+                        .filter_map(|p| Some((p.path.as_deref()?, p.port)))
+                        .chain(std::iter::once(("/", eph.spec.service.port)))
+                        .map(|(path, port)| HTTPIngressPath {
+                            path: Some(path.into()),
+                            path_type: Some("Prefix".into()),
+                            backend: IngressBackend {
+                                service: Some(IngressServiceBackend {
+                                    name: name.clone(),
+                                    port: Some(ServiceBackendPort {
+                                        number: Some(port),
+                                        name: None,
+                                    }),
                                 }),
-                            }),
-                            resource: None,
-                        },
-                    }],
+                                resource: None,
+                            },
+                        })
+                        .collect(),
                 }),
             }]),
             ..IngressSpec::default()
@@ -98,3 +164,89 @@ fn build_ingress(eph: &Ephemeron, domain: &str) -> Ingress {
         ..Ingress::default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ingress(config: &Config) -> Ingress {
+        let eph = crate::resource::test_ephemeron(serde_json::json!({
+            "image": "nginx",
+            "port": 80,
+        }));
+        build_ingress(&eph, config)
+    }
+
+    #[test]
+    fn test_has_drifted() {
+        let desired = ingress(&Config::new("example.com".to_owned()));
+        assert!(!has_drifted(&desired, &desired));
+
+        let mut current = desired.clone();
+        if let Some(rules) = current.spec.as_mut().and_then(|s| s.rules.as_mut()) {
+            rules[0].host = Some("test.example.org".to_owned());
+        }
+        assert!(has_drifted(&current, &desired));
+
+        let mut current = desired.clone();
+        current.spec.as_mut().unwrap().tls = Some(vec![IngressTLS {
+            hosts: None,
+            secret_name: Some("other".to_owned()),
+        }]);
+        assert!(has_drifted(&current, &desired));
+    }
+
+    #[test]
+    fn test_has_drifted_class() {
+        // Defaulted by the server when not set.
+        let desired = ingress(&Config::new("example.com".to_owned()));
+        let mut current = desired.clone();
+        current.spec.as_mut().unwrap().ingress_class_name = Some("nginx".to_owned());
+        assert!(!has_drifted(&current, &desired));
+
+        let mut config = Config::new("example.com".to_owned());
+        config.ingress_class_name = Some("traefik".to_owned());
+        let desired = ingress(&config);
+        assert!(has_drifted(&current, &desired));
+        assert!(!has_drifted(&desired, &desired));
+    }
+
+    #[test]
+    fn test_build_ingress_namespace() {
+        let mut config = Config::new("example.com".to_owned());
+        config.namespace = "sandboxes".to_owned();
+        let mut eph =
+            crate::resource::test_ephemeron(serde_json::json!({"image": "nginx", "port": 80}));
+        assert_eq!(
+            build_ingress(&eph, &config).namespace().as_deref(),
+            Some("sandboxes")
+        );
+        // Created in the namespace of the app.
+        eph.annotations_mut().insert(
+            crate::resource::NAMESPACE_ANNOTATION.to_owned(),
+            "ephemeron-example".to_owned(),
+        );
+        assert_eq!(
+            build_ingress(&eph, &config).namespace().as_deref(),
+            Some("ephemeron-example")
+        );
+    }
+
+    #[test]
+    fn test_build_ingress_labels() {
+        let eph = crate::resource::test_ephemeron(serde_json::json!({
+            "image": "nginx",
+            "port": 80,
+            "ingressLabels": {"team": "a", "app.kubernetes.io/managed-by": "other"},
+            "ingressAnnotations": {"nginx.ingress.kubernetes.io/ssl-redirect": "false"},
+        }));
+        let ing = build_ingress(&eph, &Config::new("example.com".to_owned()));
+        // The managed labels take precedence.
+        assert_ne!(ing.labels()["app.kubernetes.io/managed-by"], "other");
+        assert_eq!(ing.labels()["team"], "a");
+        assert_eq!(
+            ing.annotations()["nginx.ingress.kubernetes.io/ssl-redirect"],
+            "false"
+        );
+    }
+}
@@ -0,0 +1,231 @@
+// Prometheus metrics of the controller.
+use std::{collections::BTreeMap, future::Future};
+
+use futures::StreamExt;
+use kube::{
+    api::ListParams,
+    runtime::{reflector, watcher},
+    Api, Client,
+};
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::Ephemeron;
+
+// Groups are unbounded, so only the largest ones get their own series.
+// The rest are counted as `other`.
+const MAX_GROUPS: usize = 50;
+// Label value for the resources without a preset or a group.
+const NONE: &str = "none";
+const OTHER: &str = "other";
+
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+static ACTIVE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    let gauge = IntGaugeVec::new(
+        Opts::new("active", "Number of active ephemerons.").namespace("ephemeron"),
+        &["preset", "group"],
+    )
+    .expect("valid metric");
+    REGISTRY
+        .register(Box::new(gauge.clone()))
+        .expect("unique metric");
+    gauge
+});
+
+static RECONCILES: Lazy<IntCounter> = Lazy::new(|| {
+    let counter = IntCounter::with_opts(
+        Opts::new("reconciles_total", "Number of reconciliations.").namespace("ephemeron"),
+    )
+    .expect("valid metric");
+    REGISTRY
+        .register(Box::new(counter.clone()))
+        .expect("unique metric");
+    counter
+});
+
+static RECONCILE_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    let counter = IntCounterVec::new(
+        Opts::new(
+            "reconcile_errors_total",
+            "Number of failed reconciliations.",
+        )
+        .namespace("ephemeron"),
+        &["error"],
+    )
+    .expect("valid metric");
+    REGISTRY
+        .register(Box::new(counter.clone()))
+        .expect("unique metric");
+    counter
+});
+
+static STEP_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    let histogram = HistogramVec::new(
+        HistogramOpts::new(
+            "reconcile_step_duration_seconds",
+            "Duration of each step of the reconciliation.",
+        )
+        .namespace("ephemeron"),
+        &["step"],
+    )
+    .expect("valid metric");
+    REGISTRY
+        .register(Box::new(histogram.clone()))
+        .expect("unique metric");
+    histogram
+});
+
+/// Render the metrics in the Prometheus text format.
+#[must_use]
+pub fn gather() -> String {
+    Lazy::force(&ACTIVE);
+    Lazy::force(&RECONCILES);
+    Lazy::force(&RECONCILE_ERRORS);
+    Lazy::force(&STEP_DURATION);
+    let mut buffer = Vec::new();
+    if let Err(err) = TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer) {
+        tracing::warn!("failed to encode metrics: {}", err);
+    }
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+pub(super) fn count_reconcile() {
+    RECONCILES.inc();
+}
+
+pub(super) fn count_error(error: &'static str) {
+    RECONCILE_ERRORS.with_label_values(&[error]).inc();
+}
+
+/// Run the step of the reconciliation and record how long it took.
+pub(super) async fn timed<F: Future>(step: &'static str, f: F) -> F::Output {
+    let _timer = STEP_DURATION.with_label_values(&[step]).start_timer();
+    f.await
+}
+
+/// Keep the active gauges up to date with a cache of `Ephemeron`s. Never returns.
+pub(super) async fn track_active(client: Client) {
+    let writer = reflector::store::Writer::<Ephemeron>::default();
+    let store = writer.as_reader();
+    let api: Api<Ephemeron> = Api::all(client);
+    reflector(writer, watcher(api, ListParams::default()))
+        .for_each(|event| {
+            match event {
+                // Recount everything on each change, so restarts of the watch don't leave stale counts.
+                Ok(_) => update_active(&store.state()),
+                Err(err) => tracing::warn!("failed to watch for metrics: {}", err),
+            }
+            futures::future::ready(())
+        })
+        .await;
+}
+
+fn update_active(ephs: &[std::sync::Arc<Ephemeron>]) {
+    ACTIVE.reset();
+    for ((preset, group), count) in active_counts(ephs) {
+        ACTIVE.with_label_values(&[preset, group]).set(count);
+    }
+}
+
+// The number of resources by preset and group.
+fn active_counts(ephs: &[std::sync::Arc<Ephemeron>]) -> BTreeMap<(&str, &str), i64> {
+    let mut groups: BTreeMap<&str, usize> = BTreeMap::new();
+    for eph in ephs {
+        *groups.entry(eph.group().unwrap_or(NONE)).or_default() += 1;
+    }
+    let mut by_size: Vec<(&str, usize)> = groups.into_iter().collect();
+    by_size.sort_by_key(|&(_, n)| std::cmp::Reverse(n));
+    by_size.truncate(MAX_GROUPS);
+
+    let mut counts: BTreeMap<(&str, &str), i64> = BTreeMap::new();
+    for eph in ephs {
+        let group = eph.group().unwrap_or(NONE);
+        let group = if by_size.iter().any(|(g, _)| *g == group) {
+            group
+        } else {
+            OTHER
+        };
+        *counts
+            .entry((eph.preset().unwrap_or(NONE), group))
+            .or_default() += 1;
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use kube::ResourceExt;
+
+    use super::*;
+
+    fn ephemeron(preset: Option<&str>, group: Option<&str>) -> Arc<Ephemeron> {
+        let mut eph =
+            crate::resource::test_ephemeron(serde_json::json!({"image": "nginx", "port": 80}));
+        if let Some(preset) = preset {
+            eph.annotations_mut().insert(
+                crate::resource::PRESET_ANNOTATION.to_owned(),
+                preset.to_owned(),
+            );
+        }
+        if let Some(group) = group {
+            eph.labels_mut()
+                .insert(crate::resource::GROUP_LABEL.to_owned(), group.to_owned());
+        }
+        Arc::new(eph)
+    }
+
+    #[test]
+    fn test_active_counts() {
+        let ephs = vec![
+            ephemeron(Some("nginx"), Some("a")),
+            ephemeron(Some("nginx"), Some("a")),
+            ephemeron(Some("redis"), Some("a")),
+            ephemeron(None, None),
+        ];
+        assert_eq!(
+            active_counts(&ephs),
+            BTreeMap::from([(("nginx", "a"), 2), (("redis", "a"), 1), ((NONE, NONE), 1),])
+        );
+    }
+
+    #[test]
+    fn test_active_counts_other() {
+        // The largest group, and `MAX_GROUPS` groups with one each.
+        let mut ephs = vec![ephemeron(Some("nginx"), Some("large")); 2];
+        let names: Vec<String> = (0..MAX_GROUPS).map(|i| format!("g{:02}", i)).collect();
+        ephs.extend(names.iter().map(|g| ephemeron(Some("nginx"), Some(g))));
+        let counts = active_counts(&ephs);
+        assert_eq!(counts.get(&("nginx", "large")), Some(&2));
+        // One of the smallest is counted as `other`.
+        assert_eq!(counts.get(&("nginx", OTHER)), Some(&1));
+        assert_eq!(counts.len(), MAX_GROUPS + 1);
+        assert_eq!(
+            counts.values().sum::<i64>(),
+            i64::try_from(ephs.len()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_count_error() {
+        count_error("pod");
+        count_error("pod");
+        count_error("ingress");
+        let metrics = gather();
+        assert!(
+            metrics.contains(r#"ephemeron_reconcile_errors_total{error="pod"} 2"#),
+            "{}",
+            metrics
+        );
+        assert!(
+            metrics.contains(r#"ephemeron_reconcile_errors_total{error="ingress"} 1"#),
+            "{}",
+            metrics
+        );
+    }
+}
@@ -1,11 +1,11 @@
-use std::{collections::BTreeMap, sync::Arc};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
 
 use chrono::Utc;
-use futures::StreamExt;
+use futures::{Stream, StreamExt};
 use k8s_openapi::{
     api::{
         core::v1::{Pod, Service},
-        networking::v1::Ingress,
+        networking::v1::{Ingress, NetworkPolicy},
     },
     apimachinery::pkg::apis::meta::v1::OwnerReference,
 };
@@ -16,20 +16,34 @@ use kube::{
 };
 use thiserror::Error;
 
-use super::Ephemeron;
+use super::{resource::SecurityContextSpec, Ephemeron};
 mod conditions;
 mod endpoints;
 mod expiry;
 mod ingress;
+pub mod metrics;
+mod namespace;
+mod network_policy;
+mod orphans;
 mod pod;
 mod service;
 
 const PROJECT_NAME: &str = "ephemeron";
+// The field manager to correct the children with server-side apply.
+const FIELD_MANAGER: &str = "ephemeron";
+// Requeue at least this long after, so already expired ones don't busy-loop.
+const MIN_REQUEUE_INTERVAL: Duration = Duration::from_secs(1);
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("failed to delete expired resource: {0}")]
     DeleteExpired(#[source] expiry::Error),
 
+    #[error("failed to reconcile namespace: {0}")]
+    ReconcileNamespace(#[source] namespace::Error),
+
+    #[error("failed to reconcile network policy: {0}")]
+    ReconcileNetworkPolicy(#[source] network_policy::Error),
+
     #[error("failed to reconcile pod: {0}")]
     ReconcilePod(#[source] pod::Error),
 
@@ -43,38 +57,257 @@ pub enum Error {
     ReconcileEndpoints(#[source] endpoints::Error),
 }
 
+impl Error {
+    // The label of the error in the metrics. Same as the step in the duration metrics.
+    fn kind(&self) -> &'static str {
+        match self {
+            Error::DeleteExpired(_) => "expiry",
+            Error::ReconcileNamespace(_) => "namespace",
+            Error::ReconcileNetworkPolicy(_) => "network_policy",
+            Error::ReconcilePod(_) => "pod",
+            Error::ReconcileService(_) => "service",
+            Error::ReconcileIngress(_) => "ingress",
+            Error::ReconcileEndpoints(_) => "endpoints",
+        }
+    }
+}
+
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 pub type HttpClient = hyper::Client<hyper_openssl::HttpsConnector<hyper::client::HttpConnector>>;
 
-// TODO Configurable
-const NS: &str = "default";
+/// Configuration of the controller.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The main domain to use.
+    pub domain: String,
+    /// The namespace to create the children in.
+    pub namespace: String,
+    /// Labels to add to all the children.
+    /// Maps the key of the label to add to the key of the annotation or label on `Ephemeron`
+    /// to take the value from. Labels on the Pod template are also looked up.
+    pub propagated_labels: BTreeMap<String, String>,
+    /// Reconcile all the resources periodically even without any changes.
+    /// Catches up with missed watch events.
+    pub resync_interval: Option<Duration>,
+    /// Mark the resource `Failed` when the container restarted more than this.
+    pub max_container_restarts: i32,
+    /// Keep `Available` until no endpoints were ready for this duration.
+    /// Prevents flapping while the Pod is replaced.
+    pub available_grace_period: Option<Duration>,
+    /// Mark `Expiring` this long before the expiration time.
+    pub expiring_warning: Option<Duration>,
+    /// Check the resources in the desired state again at least this often.
+    /// Limits how late the expiry can be with clock skews or missed watch events.
+    pub max_requeue_interval: Duration,
+    /// Switch to `fallbackImage` when pulling the image kept failing for this duration.
+    pub image_pull_fallback_after: Duration,
+    /// The namespace of the ingress controller.
+    /// Isolated services only allow traffic from and to this namespace.
+    pub ingress_controller_namespace: String,
+    /// Labels to select the namespace of the ingress controller with.
+    /// `kubernetes.io/metadata.name` of `ingress_controller_namespace` if empty, which requires Kubernetes 1.21+.
+    pub ingress_controller_namespace_labels: BTreeMap<String, String>,
+    /// The class of the Ingresses without `ingressClassName`.
+    pub ingress_class_name: Option<String>,
+    /// Names of the Secrets to pull the images with for all the Pods.
+    pub image_pull_secrets: Vec<String>,
+    /// Labels of the nodes to schedule all the Pods to.
+    /// `nodeSelector` of the resource takes precedence.
+    pub node_selector: BTreeMap<String, String>,
+    /// The security context of the Pods without `securityContext`.
+    pub security_context: Option<SecurityContextSpec>,
+    /// Retries of the readiness probes checked from outside.
+    pub probe_retry: ProbeRetry,
+    /// Optional steps of the reconciler.
+    pub features: Features,
+}
+
+/// Exponential backoff of the readiness probes checked from outside.
+#[derive(Debug, Clone)]
+pub struct ProbeRetry {
+    /// Delay after the first failure. Doubled on each failure.
+    pub initial_delay: Duration,
+    /// The longest delay.
+    pub max_delay: Duration,
+    /// Give up and mark not `Available` after failing this many times in a row. Unlimited if `None`.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ProbeRetry {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            max_attempts: None,
+        }
+    }
+}
+
+impl ProbeRetry {
+    /// The delay before trying again after failing `attempts` times.
+    fn delay(&self, attempts: u32) -> Duration {
+        let factor = 2_u32.saturating_pow(attempts.saturating_sub(1));
+        self.initial_delay
+            .checked_mul(factor)
+            .map_or(self.max_delay, |d| d.min(self.max_delay))
+    }
+
+    fn exhausted(&self, attempts: u32) -> bool {
+        self.max_attempts.map_or(false, |max| attempts >= max)
+    }
+}
+
+/// Toggles of the optional steps. All enabled by default.
+/// Disabling one is a way out when it misbehaves, without recompiling.
+#[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Features {
+    /// GET the readiness probes through the Ingress before marking `Available`.
+    /// When disabled, the ready endpoints are enough.
+    pub probe_external_availability: bool,
+    /// Create an `Ingress` for each service.
+    pub create_ingress: bool,
+    /// Create a `NetworkPolicy` for the services with `networkIsolation`.
+    pub network_policy: bool,
+    /// Switch to `fallbackImage` when pulling the image kept failing.
+    pub image_pull_fallback: bool,
+    /// Track the metrics.
+    pub metrics: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            probe_external_availability: true,
+            create_ingress: true,
+            network_policy: true,
+            image_pull_fallback: true,
+            metrics: true,
+        }
+    }
+}
+
+impl Features {
+    /// Enable or disable the feature by its name.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError::UnknownFeature`] if there's no feature with the name.
+    pub fn set(&mut self, name: &str, enabled: bool) -> Result<(), ConfigError> {
+        let feature = match name {
+            "probe_external_availability" => &mut self.probe_external_availability,
+            "create_ingress" => &mut self.create_ingress,
+            "network_policy" => &mut self.network_policy,
+            "image_pull_fallback" => &mut self.image_pull_fallback,
+            "metrics" => &mut self.metrics,
+            _ => return Err(ConfigError::UnknownFeature(name.to_owned())),
+        };
+        *feature = enabled;
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Create a new configuration with the given domain and defaults.
+    #[must_use]
+    pub fn new(domain: String) -> Self {
+        Self {
+            domain,
+            namespace: "default".to_owned(),
+            propagated_labels: BTreeMap::new(),
+            resync_interval: None,
+            max_container_restarts: 5,
+            available_grace_period: None,
+            expiring_warning: None,
+            max_requeue_interval: Duration::from_secs(300),
+            image_pull_fallback_after: Duration::from_secs(120),
+            ingress_controller_namespace: "kube-system".to_owned(),
+            ingress_controller_namespace_labels: BTreeMap::new(),
+            ingress_class_name: None,
+            image_pull_secrets: Vec::new(),
+            node_selector: BTreeMap::new(),
+            security_context: None,
+            probe_retry: ProbeRetry::default(),
+            features: Features::default(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("hosts would be invalid DNS names with domain {0}")]
+    InvalidDomain(String),
 
-pub async fn run(client: Client, domain: String) {
+    #[error("unknown feature {0}")]
+    UnknownFeature(String),
+}
+
+impl Config {
+    /// Check that the config is usable.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ConfigError`] describing the first problem found.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        // Hosts are `{name}.{domain}`, and the API creates names with ids.
+        let host = format!("{}.{}", "x".repeat(crate::resource::ID_LEN), self.domain);
+        if !crate::resource::is_valid_host(&host) {
+            return Err(ConfigError::InvalidDomain(self.domain.clone()));
+        }
+        Ok(())
+    }
+}
+
+#[allow(clippy::missing_panics_doc)]
+pub async fn run(client: Client, config: Config) {
     let https = hyper_openssl::HttpsConnector::new().expect("https connector");
     let http_client = hyper::Client::builder().build::<_, hyper::Body>(https);
+    let resync_interval = config.resync_interval;
+    let track_metrics = config.features.metrics;
     let context = Context::new(ContextData {
         http_client,
         client: client.clone(),
-        domain,
+        config,
     });
 
-    let lp = ListParams::default();
-    Controller::<Ephemeron>::new(Api::all(client.clone()), lp.clone())
-        .owns::<Pod>(Api::namespaced(client.clone(), NS), lp.clone())
-        .owns::<Service>(Api::namespaced(client.clone(), NS), lp.clone())
-        .owns::<Ingress>(Api::namespaced(client.clone(), NS), lp)
+    // The children can be in the namespaces of the apps, so they're watched in all namespaces.
+    let managed =
+        ListParams::default().labels(&format!("app.kubernetes.io/managed-by={}", PROJECT_NAME));
+    let mut controller =
+        Controller::<Ephemeron>::new(Api::all(client.clone()), ListParams::default())
+            .owns::<Pod>(Api::all(client.clone()), managed.clone())
+            .owns::<Service>(Api::all(client.clone()), managed.clone())
+            .owns::<Ingress>(Api::all(client.clone()), managed.clone())
+            .owns::<NetworkPolicy>(Api::all(client.clone()), managed.clone());
+    orphans::delete_orphans(client.clone(), &managed).await;
+    if let Some(interval) = resync_interval {
+        controller = controller.reconcile_all_on(ticks(interval));
+    }
+    let controller = controller
         .run(reconciler, error_policy, context)
         .filter_map(|x| async move { x.ok() })
         .for_each(|(_, action)| async move {
             tracing::trace!("Reconciled: {:?}", action);
-        })
-        .await;
+        });
+    if track_metrics {
+        futures::future::join(controller, metrics::track_active(client)).await;
+    } else {
+        controller.await;
+    }
+}
+
+// Stream that yields every `interval`.
+fn ticks(interval: Duration) -> impl Stream<Item = ()> + Send + Sync + 'static {
+    futures::stream::unfold((), move |()| async move {
+        tokio::time::sleep(interval).await;
+        Some(((), ()))
+    })
 }
 
 // Data to store in context
 struct ContextData {
     client: Client,
-    domain: String,
+    config: Config,
     // Used to make GET request to `https://{host}/{probe-path}` to make sure the service is usable from outside.
     // I couldn't find a better way.
     http_client: HttpClient,
@@ -82,35 +315,56 @@ struct ContextData {
 
 #[tracing::instrument(skip(eph, ctx), level = "trace")]
 async fn reconciler(eph: Arc<Ephemeron>, ctx: Context<ContextData>) -> Result<Action> {
+    metrics::count_reconcile();
     if let Some(conditions) = eph.status.as_ref().map(|s| &s.conditions) {
         tracing::trace!("conditions: {:?}", conditions);
     }
 
-    if let Some(action) = expiry::reconcile(&eph, ctx.clone())
+    // Being deleted. Don't recreate the children the garbage collector is deleting.
+    if eph.metadata.deletion_timestamp.is_some() {
+        return Ok(Action::await_change());
+    }
+    if let Some(action) = metrics::timed("expiry", expiry::reconcile(&eph, ctx.clone()))
         .await
         .map_err(Error::DeleteExpired)?
     {
         return Ok(action);
     }
-    if let Some(action) = pod::reconcile(&eph, ctx.clone())
+    if let Some(action) = metrics::timed("namespace", namespace::reconcile(&eph, ctx.clone()))
+        .await
+        .map_err(Error::ReconcileNamespace)?
+    {
+        return Ok(action);
+    }
+    if let Some(action) = metrics::timed(
+        "network_policy",
+        network_policy::reconcile(&eph, ctx.clone()),
+    )
+    .await
+    .map_err(Error::ReconcileNetworkPolicy)?
+    {
+        return Ok(action);
+    }
+    // Boxed because it's large.
+    if let Some(action) = metrics::timed("pod", Box::pin(pod::reconcile(&eph, ctx.clone())))
         .await
         .map_err(Error::ReconcilePod)?
     {
         return Ok(action);
     }
-    if let Some(action) = service::reconcile(&eph, ctx.clone())
+    if let Some(action) = metrics::timed("service", service::reconcile(&eph, ctx.clone()))
         .await
         .map_err(Error::ReconcileService)?
     {
         return Ok(action);
     }
-    if let Some(action) = ingress::reconcile(&eph, ctx.clone())
+    if let Some(action) = metrics::timed("ingress", ingress::reconcile(&eph, ctx.clone()))
         .await
         .map_err(Error::ReconcileIngress)?
     {
         return Ok(action);
     }
-    if let Some(action) = endpoints::reconcile(&eph, ctx.clone())
+    if let Some(action) = metrics::timed("endpoints", endpoints::reconcile(&eph, ctx.clone()))
         .await
         .map_err(Error::ReconcileEndpoints)?
     {
@@ -118,18 +372,47 @@ async fn reconciler(eph: Arc<Ephemeron>, ctx: Context<ContextData>) -> Result<Ac
     }
 
     // Nothing happened in this loop, so the resource is in the desired state.
-    // Requeue around when this expires unless something else triggers reconciliation.
-    Ok(Action::requeue(
-        (eph.spec.expiration_time - Utc::now())
-            .to_std()
-            .unwrap_or_default(),
-    ))
+    // Requeue around when this expires or enters the warning window
+    // unless something else triggers reconciliation. Far ones are checked periodically.
+    let now = Utc::now();
+    let next = next_check(&eph, ctx.get_ref().config.expiring_warning, now);
+    let wait = (next - now).to_std().unwrap_or_default();
+    Ok(Action::requeue(clamp_requeue(
+        wait,
+        ctx.get_ref().config.max_requeue_interval,
+    )))
+}
+
+// Between `MIN_REQUEUE_INTERVAL` and `max`. The minimum wins if `max` is smaller.
+fn clamp_requeue(wait: Duration, max: Duration) -> Duration {
+    wait.min(max).max(MIN_REQUEUE_INTERVAL)
+}
+
+// The warning time if it's ahead. Otherwise, the expiration time.
+fn next_check(
+    eph: &Ephemeron,
+    expiring_warning: Option<Duration>,
+    now: chrono::DateTime<Utc>,
+) -> chrono::DateTime<Utc> {
+    match expiring_warning {
+        Some(warning) if warning_time(eph, warning) > now => warning_time(eph, warning),
+        _ => eph.spec.expiration_time,
+    }
+}
+
+// When the resource enters the warning window before expiring.
+fn warning_time(eph: &Ephemeron, warning: Duration) -> chrono::DateTime<Utc> {
+    chrono::Duration::from_std(warning)
+        .ok()
+        .and_then(|w| eph.spec.expiration_time.checked_sub_signed(w))
+        .unwrap_or(chrono::MIN_DATETIME)
 }
 
 #[allow(clippy::needless_pass_by_value)]
 /// An error handler called when the reconciler fails.
 fn error_policy(error: &Error, _ctx: Context<ContextData>) -> Action {
     tracing::warn!("reconciler failed: {}", error);
+    metrics::count_error(error.kind());
     Action::await_change()
 }
 
@@ -143,6 +426,46 @@ fn make_common_labels(name: &str) -> BTreeMap<String, String> {
     ])
 }
 
+// Common labels and the labels propagated from the `Ephemeron`.
+fn make_labels(eph: &Ephemeron, config: &Config) -> BTreeMap<String, String> {
+    let mut labels = make_common_labels(&eph.name());
+    for (key, from) in &config.propagated_labels {
+        let value = eph
+            .annotations()
+            .get(from)
+            .or_else(|| eph.labels().get(from))
+            .or_else(|| eph.spec.service.pod_labels.get(from))
+            .and_then(|v| sanitize_label_value(v));
+        if let Some(value) = value {
+            labels.insert(key.clone(), value);
+        }
+    }
+    labels
+}
+
+// Make the value usable as a label value by replacing invalid characters and truncating.
+// Label values must be 63 characters or less, and must begin and end with an alphanumeric character
+// with `-`, `_`, `.`, and alphanumerics between.
+fn sanitize_label_value(value: &str) -> Option<String> {
+    let value: String = value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .take(63)
+        .collect();
+    let value = value.trim_matches(|c: char| !c.is_ascii_alphanumeric());
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_owned())
+    }
+}
+
 fn to_owner_reference(eph: &Ephemeron) -> OwnerReference {
     OwnerReference {
         api_version: Ephemeron::api_version(&()).into_owned(),
@@ -153,3 +476,134 @@ fn to_owner_reference(eph: &Ephemeron) -> OwnerReference {
         block_owner_deletion: Some(true),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clamp_requeue() {
+        let max = Duration::from_secs(3600);
+        assert_eq!(
+            clamp_requeue(Duration::from_secs(600), max),
+            Duration::from_secs(600)
+        );
+        assert_eq!(clamp_requeue(Duration::from_secs(86400), max), max);
+        assert_eq!(clamp_requeue(Duration::ZERO, max), MIN_REQUEUE_INTERVAL);
+        // The minimum wins over a smaller maximum.
+        assert_eq!(
+            clamp_requeue(Duration::from_secs(600), Duration::ZERO),
+            MIN_REQUEUE_INTERVAL
+        );
+    }
+
+    #[test]
+    fn test_probe_retry_delay() {
+        let retry = ProbeRetry {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(10),
+            max_attempts: Some(3),
+        };
+        assert_eq!(retry.delay(1), Duration::from_secs(1));
+        assert_eq!(retry.delay(2), Duration::from_secs(2));
+        assert_eq!(retry.delay(4), Duration::from_secs(8));
+        assert_eq!(retry.delay(5), Duration::from_secs(10));
+        assert_eq!(retry.delay(100), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_probe_retry_exhausted() {
+        let retry = ProbeRetry {
+            max_attempts: Some(3),
+            ..ProbeRetry::default()
+        };
+        assert!(!retry.exhausted(2));
+        assert!(retry.exhausted(3));
+        assert!(!ProbeRetry::default().exhausted(u32::MAX));
+    }
+
+    #[test]
+    fn test_warning_time() {
+        let mut eph =
+            crate::resource::test_ephemeron(serde_json::json!({"image": "nginx", "port": 80}));
+        let expiration_time = eph.spec.expiration_time;
+        let warning = Duration::from_secs(600);
+        assert_eq!(
+            warning_time(&eph, warning),
+            expiration_time - chrono::Duration::minutes(10)
+        );
+        // Moves with the extended lifetime.
+        eph.spec.expiration_time = expiration_time + chrono::Duration::hours(1);
+        assert_eq!(
+            warning_time(&eph, warning),
+            expiration_time + chrono::Duration::minutes(50)
+        );
+        // Too large for the dates.
+        assert_eq!(
+            warning_time(&eph, Duration::from_secs(u64::MAX)),
+            chrono::MIN_DATETIME
+        );
+    }
+
+    #[test]
+    fn test_next_check() {
+        let eph =
+            crate::resource::test_ephemeron(serde_json::json!({"image": "nginx", "port": 80}));
+        let expiration_time = eph.spec.expiration_time;
+        let minutes = chrono::Duration::minutes;
+        let warning = Some(Duration::from_secs(600));
+        let far = expiration_time - minutes(60);
+        // Without the warning, only at the expiration.
+        assert_eq!(next_check(&eph, None, far), expiration_time);
+        // At the warning before entering the window, and at the expiration after.
+        assert_eq!(
+            next_check(&eph, warning, far),
+            expiration_time - minutes(10)
+        );
+        assert_eq!(
+            next_check(&eph, warning, expiration_time - minutes(10)),
+            expiration_time
+        );
+        assert_eq!(
+            next_check(&eph, warning, expiration_time - minutes(5)),
+            expiration_time
+        );
+    }
+
+    #[test]
+    fn test_error_kind() {
+        let api_error = || {
+            kube::Error::Api(kube::error::ErrorResponse {
+                status: "Failure".to_owned(),
+                message: "failed".to_owned(),
+                reason: "InternalError".to_owned(),
+                code: 500,
+            })
+        };
+        let kinds = [
+            Error::DeleteExpired(expiry::Error::Delete(api_error())),
+            Error::ReconcileNamespace(namespace::Error::GetNamespace(api_error())),
+            Error::ReconcileNetworkPolicy(network_policy::Error::GetNetworkPolicy(api_error())),
+            Error::ReconcilePod(pod::Error::GetPod(api_error())),
+            Error::ReconcileService(service::Error::GetService(api_error())),
+            Error::ReconcileIngress(ingress::Error::GetIngress(api_error())),
+            Error::ReconcileEndpoints(endpoints::Error::GetEndpoints(api_error())),
+        ]
+        .iter()
+        .map(Error::kind)
+        .collect::<Vec<_>>();
+        // Same as the steps in `reconciler`.
+        assert_eq!(
+            kinds,
+            [
+                "expiry",
+                "namespace",
+                "network_policy",
+                "pod",
+                "service",
+                "ingress",
+                "endpoints"
+            ]
+        );
+    }
+}
@@ -0,0 +1,64 @@
+use k8s_openapi::api::core::v1::Namespace;
+use kube::{
+    api::{ObjectMeta, PostParams},
+    error::ErrorResponse,
+    runtime::controller::{Action, Context},
+    Api,
+};
+use thiserror::Error;
+
+use super::ContextData;
+use crate::Ephemeron;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("failed to create namespace: {0}")]
+    CreateNamespace(#[source] kube::Error),
+
+    #[error("failed to get namespace: {0}")]
+    GetNamespace(#[source] kube::Error),
+}
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// Create the namespace of the app if the resource has its own.
+// Shared by the resources of the app, so it's not owned by any of them and kept after they're deleted.
+#[tracing::instrument(skip(eph, ctx), level = "trace")]
+pub(super) async fn reconcile(
+    eph: &Ephemeron,
+    ctx: Context<ContextData>,
+) -> Result<Option<Action>> {
+    let config = &ctx.get_ref().config;
+    let ns = eph.namespace_or(&config.namespace);
+    if ns == config.namespace {
+        return Ok(None);
+    }
+
+    let namespaces: Api<Namespace> = Api::all(ctx.get_ref().client.clone());
+    if namespaces
+        .get_opt(ns)
+        .await
+        .map_err(Error::GetNamespace)?
+        .is_some()
+    {
+        return Ok(None);
+    }
+    tracing::debug!("Creating Namespace {}", ns);
+    let namespace = Namespace {
+        metadata: ObjectMeta {
+            name: Some(ns.to_owned()),
+            labels: Some(super::make_common_labels(ns)),
+            ..ObjectMeta::default()
+        },
+        ..Namespace::default()
+    };
+    match namespaces.create(&PostParams::default(), &namespace).await {
+        Ok(_) => Ok(None),
+
+        Err(kube::Error::Api(ErrorResponse { code: 409, .. })) => {
+            tracing::debug!("Namespace already exists");
+            Ok(None)
+        }
+
+        Err(err) => Err(Error::CreateNamespace(err)),
+    }
+}
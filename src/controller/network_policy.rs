@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::{
+    api::networking::v1::{
+        NetworkPolicy, NetworkPolicyEgressRule, NetworkPolicyIngressRule, NetworkPolicyPeer,
+        NetworkPolicySpec,
+    },
+    apimachinery::pkg::apis::meta::v1::LabelSelector,
+};
+use kube::{
+    api::{ObjectMeta, PostParams},
+    error::ErrorResponse,
+    runtime::controller::{Action, Context},
+    Api, ResourceExt,
+};
+use thiserror::Error;
+
+use super::{conditions, Config, ContextData};
+use crate::Ephemeron;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("failed to create network policy: {0}")]
+    CreateNetworkPolicy(#[source] kube::Error),
+
+    #[error("failed to get network policy: {0}")]
+    GetNetworkPolicy(#[source] kube::Error),
+
+    #[error("failed to update condition: {0}")]
+    UpdateCondition(#[source] conditions::Error),
+}
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// Only services with `networkIsolation` have a `NetworkPolicy`.
+// Reconciled before the Pod so it never runs without the policy.
+#[tracing::instrument(skip(eph, ctx), level = "trace")]
+pub(super) async fn reconcile(
+    eph: &Ephemeron,
+    ctx: Context<ContextData>,
+) -> Result<Option<Action>> {
+    if !eph.spec.service.network_isolation {
+        return Ok(None);
+    }
+
+    let client = ctx.get_ref().client.clone();
+    // Stop here instead of running the Pod without the requested isolation.
+    if !ctx.get_ref().config.features.network_policy {
+        if eph.failure().is_none() {
+            tracing::debug!("NetworkPolicy is disabled, not creating Pod");
+            conditions::set_failed(
+                eph,
+                client,
+                Some(true),
+                "NetworkPolicyDisabled",
+                Some("networkIsolation requires network_policy feature".to_owned()),
+            )
+            .await
+            .map_err(Error::UpdateCondition)?;
+        }
+        return Ok(Some(Action::await_change()));
+    }
+
+    let name = eph.name();
+    let policies: Api<NetworkPolicy> = Api::namespaced(
+        client.clone(),
+        eph.namespace_or(&ctx.get_ref().config.namespace),
+    );
+    if policies
+        .get_opt(&name)
+        .await
+        .map_err(Error::GetNetworkPolicy)?
+        .is_some()
+    {
+        Ok(None)
+    } else {
+        tracing::debug!("Creating NetworkPolicy");
+        let policy = build_network_policy(eph, &ctx.get_ref().config);
+        match policies.create(&PostParams::default(), &policy).await {
+            Ok(_) => Ok(Some(Action::await_change())),
+
+            Err(kube::Error::Api(ErrorResponse { code: 409, .. })) => {
+                tracing::debug!("NetworkPolicy already exists");
+                Ok(Some(Action::await_change()))
+            }
+
+            Err(err) => Err(Error::CreateNetworkPolicy(err)),
+        }
+    }
+}
+
+// Allow traffic only from and to the namespace of the ingress controller.
+fn build_network_policy(eph: &Ephemeron, config: &Config) -> NetworkPolicy {
+    let name = eph.name();
+    let labels = if config.ingress_controller_namespace_labels.is_empty() {
+        BTreeMap::from([(
+            "kubernetes.io/metadata.name".to_owned(),
+            config.ingress_controller_namespace.clone(),
+        )])
+    } else {
+        config.ingress_controller_namespace_labels.clone()
+    };
+    let ingress_controller = NetworkPolicyPeer {
+        namespace_selector: Some(LabelSelector {
+            match_labels: Some(labels),
+            ..LabelSelector::default()
+        }),
+        ..NetworkPolicyPeer::default()
+    };
+    NetworkPolicy {
+        metadata: ObjectMeta {
+            name: Some(name.clone()),
+            namespace: Some(eph.namespace_or(&config.namespace).to_owned()),
+            labels: Some(super::make_labels(eph, config)),
+            owner_references: Some(vec![super::to_owner_reference(eph)]),
+            ..ObjectMeta::default()
+        },
+        spec: Some(NetworkPolicySpec {
+            pod_selector: LabelSelector {
+                match_labels: Some(BTreeMap::from([(
+                    "app.kubernetes.io/name".to_owned(),
+                    name,
+                )])),
+                ..LabelSelector::default()
+            },
+            policy_types: Some(vec!["Ingress".into(), "Egress".into()]),
+            ingress: Some(vec![NetworkPolicyIngressRule {
+                from: Some(vec![ingress_controller.clone()]),
+                ..NetworkPolicyIngressRule::default()
+            }]),
+            egress: Some(vec![NetworkPolicyEgressRule {
+                to: Some(vec![ingress_controller]),
+                ..NetworkPolicyEgressRule::default()
+            }]),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn namespace_labels(policy: &NetworkPolicy) -> BTreeMap<String, String> {
+        let spec = policy.spec.clone().unwrap();
+        let peer = &spec.ingress.unwrap()[0].from.clone().unwrap()[0];
+        peer.namespace_selector
+            .clone()
+            .unwrap()
+            .match_labels
+            .unwrap()
+    }
+
+    fn ephemeron() -> Ephemeron {
+        crate::resource::test_ephemeron(serde_json::json!({
+            "image": "nginx",
+            "port": 80,
+            "networkIsolation": true,
+        }))
+    }
+
+    #[test]
+    fn test_namespace_selector() {
+        let mut config = Config::new("example.com".to_owned());
+        let policy = build_network_policy(&ephemeron(), &config);
+        assert_eq!(
+            namespace_labels(&policy),
+            BTreeMap::from([(
+                "kubernetes.io/metadata.name".to_owned(),
+                "kube-system".to_owned()
+            )])
+        );
+
+        config.ingress_controller_namespace_labels =
+            BTreeMap::from([("name".to_owned(), "ingress".to_owned())]);
+        let policy = build_network_policy(&ephemeron(), &config);
+        assert_eq!(
+            namespace_labels(&policy),
+            BTreeMap::from([("name".to_owned(), "ingress".to_owned())])
+        );
+    }
+}
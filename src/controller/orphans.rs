@@ -0,0 +1,105 @@
+// Delete the children left behind by deleted `Ephemeron`s.
+// The garbage collector of Kubernetes normally deletes them, but the ones it missed
+// (e.g., created while the owner was being deleted) would keep the host routed.
+use std::{collections::BTreeSet, fmt::Debug};
+
+use k8s_openapi::api::{core::v1::Service, networking::v1::Ingress};
+use kube::{
+    api::{DeleteParams, ListParams, ObjectMeta},
+    Api, Client, Resource, ResourceExt,
+};
+use serde::de::DeserializeOwned;
+
+use crate::Ephemeron;
+
+/// Delete the managed `Service`s and `Ingress`es whose owner no longer exists.
+/// Failures are logged, so they don't prevent the controller from starting.
+pub(super) async fn delete_orphans(client: Client, lp: &ListParams) {
+    delete_orphaned::<Service>(client.clone(), lp).await;
+    delete_orphaned::<Ingress>(client, lp).await;
+}
+
+async fn delete_orphaned<K>(client: Client, lp: &ListParams)
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug,
+{
+    let kind = K::kind(&());
+    // Listed before the owners, so the children of the ones created in between are never selected.
+    let children = match Api::<K>::all(client.clone()).list(lp).await {
+        Ok(list) => list.items,
+        Err(err) => {
+            tracing::warn!("failed to list {} to clean up: {}", kind, err);
+            return;
+        }
+    };
+    let owners: BTreeSet<String> = match Api::<Ephemeron>::all(client.clone())
+        .list(&ListParams::default())
+        .await
+    {
+        Ok(list) => list
+            .items
+            .into_iter()
+            .filter_map(|e| e.metadata.uid)
+            .collect(),
+        Err(err) => {
+            tracing::warn!("failed to list ephemerons to clean up: {}", err);
+            return;
+        }
+    };
+
+    for child in children.iter().filter(|c| is_orphan(c.meta(), &owners)) {
+        let name = child.name();
+        let ns = child.namespace().unwrap_or_default();
+        tracing::info!("deleting orphaned {} {}/{}", kind, ns, name);
+        let api: Api<K> = Api::namespaced(client.clone(), &ns);
+        if let Err(err) = api.delete(&name, &DeleteParams::background()).await {
+            tracing::warn!("failed to delete {} {}/{}: {}", kind, ns, name, err);
+        }
+    }
+}
+
+// `true` if owned by an `Ephemeron` that's not in `owners`. Ones without an `Ephemeron` owner are kept.
+fn is_orphan(meta: &ObjectMeta, owners: &BTreeSet<String>) -> bool {
+    let api_version = Ephemeron::api_version(&());
+    let kind = Ephemeron::kind(&());
+    meta.owner_references
+        .iter()
+        .flatten()
+        .find(|r| r.api_version == api_version && r.kind == kind)
+        .map_or(false, |r| !owners.contains(&r.uid))
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+
+    use super::*;
+
+    fn owned_by(owner: OwnerReference) -> ObjectMeta {
+        ObjectMeta {
+            owner_references: Some(vec![owner]),
+            ..ObjectMeta::default()
+        }
+    }
+
+    #[test]
+    fn test_is_orphan() {
+        let eph =
+            crate::resource::test_ephemeron(serde_json::json!({"image": "nginx", "port": 80}));
+        let meta = owned_by(super::super::to_owner_reference(&eph));
+        let existing = BTreeSet::from(["test-uid".to_owned()]);
+        assert!(!is_orphan(&meta, &existing));
+        assert!(is_orphan(&meta, &BTreeSet::new()));
+
+        // Not managed by the controller.
+        let other = owned_by(OwnerReference {
+            api_version: "apps/v1".to_owned(),
+            kind: "Deployment".to_owned(),
+            name: "other".to_owned(),
+            uid: "other-uid".to_owned(),
+            ..OwnerReference::default()
+        });
+        assert!(!is_orphan(&other, &BTreeSet::new()));
+        assert!(!is_orphan(&ObjectMeta::default(), &BTreeSet::new()));
+    }
+}
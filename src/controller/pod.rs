@@ -1,17 +1,43 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
+
+use chrono::Utc;
 use k8s_openapi::{
-    api::core::v1::{Container, ContainerPort, EnvVar, HTTPGetAction, Pod, PodSpec, Probe},
-    apimachinery::pkg::util::intstr::IntOrString,
+    api::core::v1::{
+        Container, ContainerPort, EmptyDirVolumeSource, EnvVar, HTTPGetAction,
+        LocalObjectReference, Pod, PodSecurityContext, PodSpec, Probe, ProjectedVolumeSource,
+        SecurityContext, ServiceAccountTokenProjection, Volume, VolumeMount, VolumeProjection,
+    },
+    apimachinery::pkg::{api::resource::Quantity, util::intstr::IntOrString},
 };
 use kube::{
-    api::{ObjectMeta, PostParams},
+    api::{DeleteParams, ObjectMeta, Patch, PatchParams, PostParams},
     error::ErrorResponse,
     runtime::controller::{Action, Context},
-    Api, ResourceExt,
+    Api, Client, ResourceExt,
 };
 use thiserror::Error;
 
-use super::{conditions, ContextData};
-use crate::Ephemeron;
+use super::{conditions, Config, ContextData};
+use crate::{resource::HttpGetProbe, Ephemeron, SecurityContextSpec};
+
+// The name of the main container. Also used by the API to exec and get logs.
+const CONTAINER_NAME: &str = "container";
+// The name of the volume for the projected service account token.
+const SERVICE_ACCOUNT_TOKEN_VOLUME: &str = "ephemeron-sa-token";
+// Annotation on the Pod with the hash of the spec it was created with.
+// The Pod is recreated when the spec built from the `Ephemeron` changes.
+// Compared with the annotation because the Pod's spec can be defaulted by the API server and `LimitRange`.
+const SPEC_HASH_ANNOTATION: &str = "ephemerons.qualified.io/spec-hash";
+// Pods younger than this are not replaced on spec changes yet,
+// so a spec that keeps changing doesn't delete and recreate the Pod in a tight loop.
+const MIN_REPLACE_AGE: Duration = Duration::from_secs(10);
+// The reason of the conditions while paused.
+const PAUSED: &str = "Paused";
+// Annotation set to the original image when the Pod was replaced to use `fallbackImage`.
+const FALLBACK_FROM: &str = "ephemerons.qualified.io/fallback-from";
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -21,6 +47,15 @@ pub enum Error {
     #[error("failed to get god: {0}")]
     GetPod(#[source] kube::Error),
 
+    #[error("failed to delete pod: {0}")]
+    DeletePod(#[source] kube::Error),
+
+    #[error("failed to annotate: {0}")]
+    Annotate(#[source] kube::Error),
+
+    #[error("failed to annotate pod: {0}")]
+    AnnotatePod(#[source] kube::Error),
+
     #[error("failed to update condition: {0}")]
     UpdateCondition(#[source] conditions::Error),
 }
@@ -34,25 +69,111 @@ pub(super) async fn reconcile(
     let name = eph.name();
     let client = ctx.get_ref().client.clone();
 
-    let pods: Api<Pod> = Api::namespaced(client.clone(), super::NS);
+    let pods: Api<Pod> = Api::namespaced(
+        client.clone(),
+        eph.namespace_or(&ctx.get_ref().config.namespace),
+    );
+    if eph.spec.paused {
+        return pause(eph, client, &pods).await;
+    }
     if let Some(pod) = pods.get_opt(&name).await.map_err(Error::GetPod)? {
-        match (eph.is_pod_ready(), pod_is_ready(&pod)) {
-            (a, b) if a == b => Ok(None),
-            (_, actual) => {
-                conditions::set_pod_ready(eph, ctx.get_ref().client.clone(), Some(actual))
+        if eph.failure().is_none() {
+            let max_restarts = ctx.get_ref().config.max_container_restarts;
+            if let Some((reason, message)) = restart_limit_exceeded(&pod, max_restarts) {
+                tracing::debug!("Pod restarted too many times");
+                conditions::set_failed(eph, client, Some(true), &reason, Some(message))
                     .await
                     .map_err(Error::UpdateCondition)?;
-                Ok(Some(Action::await_change()))
+                return Ok(Some(Action::await_change()));
             }
         }
-    } else {
-        conditions::set_pod_ready(eph, client.clone(), Some(false))
+
+        let config = &ctx.get_ref().config;
+        if config.features.image_pull_fallback
+            && should_fall_back(eph, &pod, config.image_pull_fallback_after)
+        {
+            tracing::debug!("Failed to pull image, replacing Pod to use the fallback image");
+            pods.delete(&name, &DeleteParams::default())
+                .await
+                .map_err(Error::DeletePod)?;
+            // Annotate after deleting, so failing to delete is retried.
+            annotate(eph, client, FALLBACK_FROM, &eph.spec.service.image).await?;
+            return Ok(Some(Action::await_change()));
+        }
+
+        let hash = spec_hash(eph);
+        if !pod.annotations().contains_key(SPEC_HASH_ANNOTATION) {
+            // Created before recording the hash. Assume it's up to date instead of replacing
+            // the Pods of all the existing ones at once.
+            tracing::debug!("Recording spec hash on Pod");
+            pods.patch(
+                &name,
+                &PatchParams::default(),
+                &Patch::Merge(serde_json::json!({
+                    "metadata": { "annotations": { SPEC_HASH_ANNOTATION: hash } },
+                })),
+            )
             .await
-            .map_err(Error::UpdateCondition)?;
-        conditions::set_available(eph, client.clone(), Some(false))
+            .map_err(Error::AnnotatePod)?;
+        } else if is_outdated(&pod, &hash) {
+            // Already being replaced.
+            if pod.metadata.deletion_timestamp.is_some() {
+                return Ok(Some(Action::await_change()));
+            }
+            if let Some(wait) = MIN_REPLACE_AGE.checked_sub(pod_age(&pod)) {
+                if !wait.is_zero() {
+                    return Ok(Some(Action::requeue(wait)));
+                }
+            }
+            tracing::debug!("Spec changed, replacing Pod");
+            pods.delete(&name, &DeleteParams::default())
+                .await
+                .map_err(Error::DeletePod)?;
+            conditions::set_pod_ready(eph, client.clone(), Some(false), "PodReplacing", None)
+                .await
+                .map_err(Error::UpdateCondition)?;
+            if ctx.get_ref().config.available_grace_period.is_none() {
+                conditions::set_available(eph, client, Some(false), "PodReplacing", None)
+                    .await
+                    .map_err(Error::UpdateCondition)?;
+            }
+            return Ok(Some(Action::await_change()));
+        }
+
+        let (ready, reason, message) = pod_readiness(&pod);
+        if eph.is_pod_ready() == ready && eph.pod_ready_reason() == Some(&reason) {
+            Ok(None)
+        } else {
+            conditions::set_pod_ready(eph, client, Some(ready), &reason, message)
+                .await
+                .map_err(Error::UpdateCondition)?;
+            Ok(Some(Action::await_change()))
+        }
+    } else {
+        if let Err((reason, message)) = validate(eph) {
+            tracing::debug!("{}: {}", reason, message);
+            if eph.failure().is_none() {
+                conditions::set_failed(eph, client, Some(true), reason, Some(message))
+                    .await
+                    .map_err(Error::UpdateCondition)?;
+            }
+            return Ok(Some(Action::await_change()));
+        }
+        conditions::set_pod_ready(eph, client.clone(), Some(false), "PodCreating", None)
             .await
             .map_err(Error::UpdateCondition)?;
-        let pod = build_pod(eph);
+        // With the grace period, `Available` is updated when no endpoints were ready for the period.
+        if ctx.get_ref().config.available_grace_period.is_none() {
+            conditions::set_available(eph, client.clone(), Some(false), "PodCreating", None)
+                .await
+                .map_err(Error::UpdateCondition)?;
+        }
+        if eph.failure().is_some() {
+            conditions::set_failed(eph, client.clone(), Some(false), "PodCreating", None)
+                .await
+                .map_err(Error::UpdateCondition)?;
+        }
+        let pod = build_pod(eph, &ctx.get_ref().config);
         match pods.create(&PostParams::default(), &pod).await {
             Ok(_) => Ok(Some(Action::await_change())),
             Err(kube::Error::Api(ErrorResponse { code: 409, .. })) => {
@@ -64,78 +185,1006 @@ pub(super) async fn reconcile(
     }
 }
 
-fn build_pod(eph: &Ephemeron) -> Pod {
+// Delete the Pod and mark not ready. The rest is kept, and the Pod is recreated when resumed.
+async fn pause(eph: &Ephemeron, client: Client, pods: &Api<Pod>) -> Result<Option<Action>> {
+    let name = eph.name();
+    if let Some(pod) = pods.get_opt(&name).await.map_err(Error::GetPod)? {
+        if pod.metadata.deletion_timestamp.is_none() {
+            tracing::debug!("Paused, deleting Pod");
+            pods.delete(&name, &DeleteParams::default())
+                .await
+                .map_err(Error::DeletePod)?;
+        }
+    }
+    match paused_condition(eph) {
+        Some(PausedCondition::PodReady) => {
+            conditions::set_pod_ready(eph, client, Some(false), PAUSED, None)
+                .await
+                .map_err(Error::UpdateCondition)?;
+        }
+        Some(PausedCondition::Available) => {
+            conditions::set_available(eph, client, Some(false), PAUSED, None)
+                .await
+                .map_err(Error::UpdateCondition)?;
+        }
+        None => return Ok(None),
+    }
+    Ok(Some(Action::await_change()))
+}
+
+#[derive(Debug, PartialEq)]
+enum PausedCondition {
+    PodReady,
+    Available,
+}
+
+// The next condition to set to `False` with `Paused`. `None` if both are.
+// `Available` is set without the grace period because it's not coming back until resumed.
+fn paused_condition(eph: &Ephemeron) -> Option<PausedCondition> {
+    if eph.pod_ready_reason() != Some(PAUSED) {
+        Some(PausedCondition::PodReady)
+    } else if eph.available_reason() != Some(PAUSED) {
+        Some(PausedCondition::Available)
+    } else {
+        None
+    }
+}
+
+fn build_pod(eph: &Ephemeron, config: &Config) -> Pod {
     let name = eph.name();
     let mut labels = eph.spec.service.pod_labels.clone();
-    labels.append(&mut super::make_common_labels(&name));
+    labels.append(&mut super::make_labels(eph, config));
     Pod {
         metadata: ObjectMeta {
             name: Some(name),
-            namespace: Some(super::NS.into()),
+            namespace: Some(eph.namespace_or(&config.namespace).to_owned()),
             owner_references: Some(vec![super::to_owner_reference(eph)]),
             labels: Some(labels),
+            annotations: Some(BTreeMap::from([(
+                SPEC_HASH_ANNOTATION.to_owned(),
+                spec_hash(eph),
+            )])),
             ..ObjectMeta::default()
         },
-        spec: Some(PodSpec {
-            containers: vec![Container {
-                name: "container".into(),
-                image: Some(eph.spec.service.image.clone()),
-                image_pull_policy: eph.spec.service.image_pull_policy.clone(),
-                // Note that `command` in Kubernetes corresponds to `Entrypoint` in Docker, and
-                // `args` corresponds to `Cmd` in Docker.
-                // See https://kubernetes.io/docs/tasks/inject-data-application/define-command-argument-container/#notes
-                //
-                // If `command` is specified without `args`, only the supplied `command` is used.
-                // The default `Entrypoint` and `Cmd` are ignored.
-                // If `command` is not specified, the default `EntryPoint` and `Cmd` are used.
-                command: Some(eph.spec.service.command.clone().unwrap_or_default()),
-                env: eph.spec.service.env.clone().map(|v| {
-                    v.into_iter()
-                        .map(|e| EnvVar {
-                            name: e.name,
-                            value: e.value,
-                            value_from: None,
-                        })
-                        .collect()
-                }),
-                working_dir: eph.spec.service.working_dir.clone(),
-                ports: Some(vec![ContainerPort {
+        spec: Some(build_pod_spec(eph, config)),
+        ..Pod::default()
+    }
+}
+
+fn build_pod_spec(eph: &Ephemeron, config: &Config) -> PodSpec {
+    PodSpec {
+        containers: vec![Container {
+            name: CONTAINER_NAME.into(),
+            image: Some(image(eph).to_owned()),
+            image_pull_policy: eph.spec.service.image_pull_policy.clone(),
+            // Note that `command` in Kubernetes corresponds to `Entrypoint` in Docker, and
+            // `args` corresponds to `Cmd` in Docker.
+            // See https://kubernetes.io/docs/tasks/inject-data-application/define-command-argument-container/#notes
+            //
+            // If `command` is specified without `args`, only the supplied `command` is used.
+            // The default `Entrypoint` and `Cmd` are ignored.
+            // If `command` is not specified, the default `EntryPoint` and `Cmd` are used.
+            command: Some(eph.spec.service.command.clone().unwrap_or_default()),
+            env: to_env(eph.spec.service.env.as_deref()),
+            working_dir: eph.spec.service.working_dir.clone(),
+            ports: Some(
+                std::iter::once(ContainerPort {
                     container_port: eph.spec.service.port,
+                    // Unset unless specified. Defaulted to `TCP` by the server.
+                    protocol: eph.spec.service.protocol.clone(),
                     ..ContainerPort::default()
-                }]),
-                readiness_probe: eph
-                    .spec
-                    .service
-                    .readiness_probe
-                    .as_ref()
-                    .map(|probe| Probe {
-                        http_get: Some(HTTPGetAction {
-                            path: Some(probe.path.clone()),
-                            port: IntOrString::Int(eph.spec.service.port),
-                            ..HTTPGetAction::default()
+                })
+                .chain(
+                    eph.spec
+                        .service
+                        .additional_ports
+                        .iter()
+                        .map(|p| ContainerPort {
+                            name: Some(p.name.clone()),
+                            container_port: p.port,
+                            protocol: p.protocol.clone(),
+                            ..ContainerPort::default()
                         }),
-                        initial_delay_seconds: probe.initial_delay_seconds,
-                        period_seconds: probe.period_seconds,
-                        timeout_seconds: probe.timeout_seconds,
-                        ..Probe::default()
+                )
+                .collect(),
+            ),
+            // Containers can only have one readiness probe. The rest is checked by the controller.
+            readiness_probe: eph.readiness_probes().next().map(|p| to_probe(eph, p)),
+            startup_probe: eph.spec.service.startup_probe.as_ref().map(|p| Probe {
+                // Must be 1 for startup and liveness probes.
+                success_threshold: None,
+                ..to_probe(eph, p)
+            }),
+            liveness_probe: eph.spec.service.liveness_probe.as_ref().map(|p| Probe {
+                success_threshold: None,
+                ..to_probe(eph, p)
+            }),
+            resources: eph.spec.service.resources.clone(),
+            stdin: eph.spec.service.stdin,
+            tty: eph.spec.service.tty,
+            volume_mounts: non_empty(volume_mounts(eph)),
+            security_context: container_security_context(eph, config),
+            ..Container::default()
+        }],
+        init_containers: eph.spec.service.init_containers.as_ref().map(|cs| {
+            cs.iter()
+                .map(|c| Container {
+                    name: c.name.clone(),
+                    image: Some(c.image.clone()),
+                    image_pull_policy: eph.spec.service.image_pull_policy.clone(),
+                    command: Some(c.command.clone().unwrap_or_default()),
+                    env: to_env(c.env.as_deref()),
+                    working_dir: c.working_dir.clone(),
+                    resources: c.resources.clone(),
+                    // Share the volumes to prepare files for the container.
+                    volume_mounts: non_empty(volume_mounts(eph)),
+                    security_context: container_security_context(eph, config),
+                    ..Container::default()
+                })
+                .collect()
+        }),
+        volumes: non_empty(volumes(eph)),
+        image_pull_secrets: non_empty(image_pull_secrets(eph, config)),
+        node_selector: node_selector(eph, config),
+        tolerations: eph.spec.service.tolerations.clone(),
+        security_context: pod_security_context(eph, config),
+        restart_policy: Some("Always".into()),
+        // Don't inject information about services.
+        enable_service_links: Some(false),
+        automount_service_account_token: eph.spec.service.network_isolation.then(|| false),
+        ..PodSpec::default()
+    }
+}
+
+fn volumes(eph: &Ephemeron) -> Vec<Volume> {
+    let token = eph
+        .spec
+        .service
+        .service_account_token
+        .as_ref()
+        .map(|t| Volume {
+            name: SERVICE_ACCOUNT_TOKEN_VOLUME.into(),
+            projected: Some(ProjectedVolumeSource {
+                sources: Some(vec![VolumeProjection {
+                    service_account_token: Some(ServiceAccountTokenProjection {
+                        audience: Some(t.audience.clone()),
+                        expiration_seconds: t.expiration_seconds,
+                        path: "token".into(),
                     }),
-                resources: eph.spec.service.resources.clone(),
-                ..Container::default()
-            }],
-            restart_policy: Some("Always".into()),
-            // Don't inject information about services.
-            enable_service_links: Some(false),
-            ..PodSpec::default()
+                    ..VolumeProjection::default()
+                }]),
+                ..ProjectedVolumeSource::default()
+            }),
+            ..Volume::default()
+        });
+    let empty_dirs = eph.spec.service.volumes.iter().flatten().map(|v| Volume {
+        name: v.name.clone(),
+        empty_dir: Some(EmptyDirVolumeSource {
+            size_limit: v.size_limit.clone().map(Quantity),
+            ..EmptyDirVolumeSource::default()
         }),
-        ..Pod::default()
+        ..Volume::default()
+    });
+    token.into_iter().chain(empty_dirs).collect()
+}
+
+fn volume_mounts(eph: &Ephemeron) -> Vec<VolumeMount> {
+    let token = eph
+        .spec
+        .service
+        .service_account_token
+        .as_ref()
+        .map(|t| VolumeMount {
+            name: SERVICE_ACCOUNT_TOKEN_VOLUME.into(),
+            mount_path: t.mount_path.clone(),
+            read_only: Some(true),
+            ..VolumeMount::default()
+        });
+    let empty_dirs = eph
+        .spec
+        .service
+        .volumes
+        .iter()
+        .flatten()
+        .map(|v| VolumeMount {
+            name: v.name.clone(),
+            mount_path: v.mount_path.clone(),
+            ..VolumeMount::default()
+        });
+    token.into_iter().chain(empty_dirs).collect()
+}
+
+// The default ones followed by the ones in spec without duplicates.
+fn image_pull_secrets(eph: &Ephemeron, config: &Config) -> Vec<LocalObjectReference> {
+    let mut seen = BTreeSet::new();
+    config
+        .image_pull_secrets
+        .iter()
+        .chain(eph.spec.service.image_pull_secrets.iter().flatten())
+        .filter(|name| seen.insert(name.as_str()))
+        .map(|name| LocalObjectReference {
+            name: Some(name.clone()),
+        })
+        .collect()
+}
+
+// HTTP GET on `port`. `scheme` is only for the checks from outside.
+fn to_probe(eph: &Ephemeron, probe: &HttpGetProbe) -> Probe {
+    Probe {
+        http_get: Some(HTTPGetAction {
+            path: Some(probe.path.clone()),
+            port: IntOrString::Int(eph.spec.service.port),
+            ..HTTPGetAction::default()
+        }),
+        initial_delay_seconds: probe.initial_delay_seconds,
+        period_seconds: probe.period_seconds,
+        timeout_seconds: probe.timeout_seconds,
+        success_threshold: probe.success_threshold,
+        failure_threshold: probe.failure_threshold,
+        ..Probe::default()
     }
 }
 
-fn pod_is_ready(pod: &Pod) -> bool {
-    pod.status
+// The default of the controller merged with the resource's. `arch` takes precedence.
+fn node_selector(eph: &Ephemeron, config: &Config) -> Option<BTreeMap<String, String>> {
+    let mut selector = config.node_selector.clone();
+    selector.extend(eph.spec.service.node_selector.clone());
+    if let Some(arch) = &eph.spec.service.arch {
+        selector.insert("kubernetes.io/arch".to_owned(), arch.clone());
+    }
+    (!selector.is_empty()).then(|| selector)
+}
+
+// `securityContext` of the resource, or the default of the controller.
+fn security_context<'a>(eph: &'a Ephemeron, config: &'a Config) -> Option<&'a SecurityContextSpec> {
+    eph.spec
+        .service
+        .security_context
         .as_ref()
-        .and_then(|s| s.conditions.as_ref())
+        .or(config.security_context.as_ref())
+}
+
+// The users apply to all the containers, so they're set on the Pod.
+fn pod_security_context(eph: &Ephemeron, config: &Config) -> Option<PodSecurityContext> {
+    security_context(eph, config).map(|sc| PodSecurityContext {
+        run_as_non_root: sc.run_as_non_root,
+        run_as_user: sc.run_as_user,
+        run_as_group: sc.run_as_group,
+        fs_group: sc.fs_group,
+        ..PodSecurityContext::default()
+    })
+}
+
+// Read-only root filesystem is only available per container.
+fn container_security_context(eph: &Ephemeron, config: &Config) -> Option<SecurityContext> {
+    security_context(eph, config)
+        .and_then(|sc| sc.read_only_root_filesystem)
+        .map(|read_only| SecurityContext {
+            read_only_root_filesystem: Some(read_only),
+            ..SecurityContext::default()
+        })
+}
+
+fn non_empty<T>(v: Vec<T>) -> Option<Vec<T>> {
+    if v.is_empty() {
+        None
+    } else {
+        Some(v)
+    }
+}
+
+// Check what the schema can't. Returns the reason and the message if invalid.
+fn validate(eph: &Ephemeron) -> Result<(), (&'static str, String)> {
+    validate_volumes(eph).map_err(|m| ("InvalidVolumes", m))?;
+    validate_ports(eph).map_err(|m| ("InvalidPorts", m))?;
+    validate_init_containers(eph).map_err(|m| ("InvalidInitContainers", m))
+}
+
+// Init containers must have unique names, and not the name of the main container.
+fn validate_init_containers(eph: &Ephemeron) -> Result<(), String> {
+    let mut names = BTreeSet::from([CONTAINER_NAME]);
+    for c in eph.spec.service.init_containers.iter().flatten() {
+        if !names.insert(&c.name) {
+            return Err(format!("init container name {} is not unique", c.name));
+        }
+    }
+    Ok(())
+}
+
+// Ports must have unique names, numbers, and paths. `main` is the name of `port` on the Service,
+// and `/` is the path of it on the Ingress.
+fn validate_ports(eph: &Ephemeron) -> Result<(), String> {
+    let mut names = BTreeSet::from([super::service::MAIN_PORT]);
+    let mut numbers = BTreeSet::from([(eph.spec.service.port, eph.protocol())]);
+    let mut paths = BTreeSet::from(["/"]);
+    for p in &eph.spec.service.additional_ports {
+        if !names.insert(&p.name) {
+            return Err(format!("port name {} is not unique", p.name));
+        }
+        if !numbers.insert((p.port, p.protocol())) {
+            return Err(format!("port {}/{} is not unique", p.port, p.protocol()));
+        }
+        if p.path.is_some() && p.protocol() != "TCP" {
+            return Err(format!(
+                "port {} with {} can't have path",
+                p.name,
+                p.protocol()
+            ));
+        }
+        if let Some(path) = &p.path {
+            if !path.starts_with('/') {
+                return Err(format!("path {} of port {} is not absolute", path, p.name));
+            }
+            if !paths.insert(path) {
+                return Err(format!("path {} of port {} is not unique", path, p.name));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Volumes must have unique names and absolute mount paths.
+// The Pod would be rejected otherwise, so check before creating to tell why.
+fn validate_volumes(eph: &Ephemeron) -> Result<(), String> {
+    let mut names = BTreeSet::new();
+    let mut paths = BTreeSet::new();
+    if let Some(t) = &eph.spec.service.service_account_token {
+        names.insert(SERVICE_ACCOUNT_TOKEN_VOLUME);
+        paths.insert(t.mount_path.as_str());
+    }
+    for v in eph.spec.service.volumes.iter().flatten() {
+        if !names.insert(&v.name) {
+            return Err(format!("volume name {} is not unique", v.name));
+        }
+        if !v.mount_path.starts_with('/') {
+            return Err(format!("mount path {} is not absolute", v.mount_path));
+        }
+        if !paths.insert(&v.mount_path) {
+            return Err(format!("mount path {} is not unique", v.mount_path));
+        }
+    }
+    Ok(())
+}
+
+fn to_env(env: Option<&[crate::resource::EnvVar]>) -> Option<Vec<EnvVar>> {
+    env.map(|v| {
+        v.iter()
+            .map(|e| EnvVar {
+                name: e.name.clone(),
+                value: e.value.clone(),
+                value_from: None,
+            })
+            .collect()
+    })
+}
+
+// FNV-1a of the fields of the service the Pod is built from. Not `DefaultHasher` because it must be
+// stable across releases. The defaults from the controller's config are not included, so changing them
+// only applies to the new Pods instead of restarting all of them. Unset fields are skipped, so adding
+// fields doesn't change the hash of the existing ones.
+fn spec_hash(eph: &Ephemeron) -> String {
+    let s = &eph.spec.service;
+    let fields = serde_json::json!({
+        "image": s.image,
+        "command": s.command,
+        "workingDir": s.working_dir,
+        "port": s.port,
+        "protocol": s.protocol,
+        "additionalPorts": s.additional_ports,
+        "readinessProbe": s.readiness_probe,
+        "startupProbe": s.startup_probe,
+        "livenessProbe": s.liveness_probe,
+        "imagePullPolicy": s.image_pull_policy,
+        "imagePullSecrets": s.image_pull_secrets,
+        "resources": s.resources,
+        "env": s.env,
+        "arch": s.arch,
+        "nodeSelector": s.node_selector,
+        "tolerations": s.tolerations,
+        "serviceAccountToken": s.service_account_token,
+        "stdin": s.stdin,
+        "tty": s.tty,
+        "networkIsolation": s.network_isolation,
+        "initContainers": s.init_containers,
+        "volumes": s.volumes,
+        "securityContext": s.security_context,
+    });
+    let fields: serde_json::Map<_, _> = fields
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(_, v)| !v.is_null())
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    crate::resource::hash(&serde_json::to_vec(&fields).unwrap_or_default())
+}
+
+// `true` if `pod` was built from a different spec. Pods without the hash are not, see `reconcile`.
+fn is_outdated(pod: &Pod, hash: &str) -> bool {
+    pod.annotations()
+        .get(SPEC_HASH_ANNOTATION)
+        .map_or(false, |h| h != hash)
+}
+
+fn pod_age(pod: &Pod) -> Duration {
+    pod.metadata
+        .creation_timestamp
+        .as_ref()
+        .and_then(|t| (Utc::now() - t.0).to_std().ok())
+        .unwrap_or_default()
+}
+
+// The image to use. `fallbackImage` if it was already switched.
+fn image(eph: &Ephemeron) -> &str {
+    match &eph.spec.service.fallback_image {
+        Some(fallback) if eph.annotations().contains_key(FALLBACK_FROM) => fallback,
+        _ => &eph.spec.service.image,
+    }
+}
+
+// `true` if the Pod should be replaced to use `fallbackImage`.
+// Only pull failures count, and the Pod must have been failing to pull for `after`.
+// Checked again when the kubelet retries and updates the Pod status.
+fn should_fall_back(eph: &Ephemeron, pod: &Pod, after: Duration) -> bool {
+    if eph.spec.service.fallback_image.is_none() || eph.annotations().contains_key(FALLBACK_FROM) {
+        return false;
+    }
+    let pull_failed = pod
+        .status
+        .as_ref()
+        .and_then(|s| s.container_statuses.as_ref())
         .map_or(false, |cs| {
-            cs.iter().any(|c| c.type_ == "Ready" && c.status == "True")
+            cs.iter().any(|c| {
+                c.state
+                    .as_ref()
+                    .and_then(|s| s.waiting.as_ref())
+                    .and_then(|w| w.reason.as_deref())
+                    .map_or(false, |r| r == "ImagePullBackOff" || r == "ErrImagePull")
+            })
+        });
+    pull_failed && pod_age(pod) >= after
+}
+
+async fn annotate(eph: &Ephemeron, client: Client, key: &str, value: &str) -> Result<()> {
+    let api: Api<Ephemeron> = Api::all(client);
+    api.patch(
+        &eph.name(),
+        &PatchParams::default(),
+        &Patch::Merge(serde_json::json!({
+            "metadata": { "annotations": { key: value } },
+        })),
+    )
+    .await
+    .map_err(Error::Annotate)?;
+    Ok(())
+}
+
+// Returns if the pod is ready, and the reason and message explaining it.
+// The reason is taken from the first waiting container (e.g., `ImagePullBackOff`),
+// or the `Ready` condition of the pod.
+fn pod_readiness(pod: &Pod) -> (bool, String, Option<String>) {
+    let status = pod.status.as_ref();
+    let ready = status
+        .and_then(|s| s.conditions.as_ref())
+        .and_then(|cs| cs.iter().find(|c| c.type_ == "Ready"));
+    if ready.map_or(false, |c| c.status == "True") {
+        return (true, "PodReady".to_owned(), None);
+    }
+
+    if let Some(waiting) = status
+        .and_then(|s| s.container_statuses.as_ref())
+        .and_then(|cs| {
+            cs.iter()
+                .find_map(|c| c.state.as_ref().and_then(|s| s.waiting.as_ref()))
         })
+    {
+        if let Some(reason) = waiting.reason.clone() {
+            return (false, reason, waiting.message.clone());
+        }
+    }
+
+    match ready.and_then(|c| c.reason.clone()) {
+        Some(reason) => (false, reason, ready.and_then(|c| c.message.clone())),
+        None => (false, "PodNotReady".to_owned(), None),
+    }
+}
+
+// Returns the reason and message if any container restarted more than `max_restarts` times.
+// The reason is the last termination reason of the container (e.g., `OOMKilled`).
+fn restart_limit_exceeded(pod: &Pod, max_restarts: i32) -> Option<(String, String)> {
+    let status = pod
+        .status
+        .as_ref()
+        .and_then(|s| s.container_statuses.as_ref())
+        .and_then(|cs| cs.iter().find(|c| c.restart_count > max_restarts))?;
+    let terminated = status
+        .last_state
+        .as_ref()
+        .and_then(|s| s.terminated.as_ref());
+    let reason = terminated
+        .and_then(|t| t.reason.clone())
+        .unwrap_or_else(|| "RestartLimitExceeded".to_owned());
+    let message = match terminated {
+        Some(t) => format!(
+            "container restarted {} times, last exited with {}{}",
+            status.restart_count,
+            t.exit_code,
+            t.message
+                .as_ref()
+                .map_or_else(String::new, |m| format!(": {}", m))
+        ),
+        None => format!("container restarted {} times", status.restart_count),
+    };
+    Some((reason, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ephemeron() -> Ephemeron {
+        crate::resource::test_ephemeron(serde_json::json!({ "image": "nginx", "port": 80 }))
+    }
+
+    #[test]
+    fn test_build_pod_namespace() {
+        let mut config = Config::new("example.com".to_owned());
+        config.namespace = "sandboxes".to_owned();
+        let mut eph =
+            crate::resource::test_ephemeron(serde_json::json!({"image": "nginx", "port": 80}));
+        assert_eq!(
+            build_pod(&eph, &config).namespace().as_deref(),
+            Some("sandboxes")
+        );
+        // Created in the namespace of the app.
+        eph.annotations_mut().insert(
+            crate::resource::NAMESPACE_ANNOTATION.to_owned(),
+            "ephemeron-example".to_owned(),
+        );
+        assert_eq!(
+            build_pod(&eph, &config).namespace().as_deref(),
+            Some("ephemeron-example")
+        );
+    }
+
+    #[test]
+    fn test_spec_hash() {
+        let mut eph = ephemeron();
+        let hash = spec_hash(&eph);
+        assert_eq!(hash, spec_hash(&eph));
+        eph.spec.service.image = "httpd".to_owned();
+        assert_ne!(hash, spec_hash(&eph));
+    }
+
+    #[test]
+    fn test_spec_hash_ignores_rest() {
+        let eph = ephemeron();
+        let hash = spec_hash(&eph);
+        // Fields not used by the Pod.
+        let mut other = eph.clone();
+        other.spec.service.service_type = Some("NodePort".to_owned());
+        other
+            .spec
+            .service
+            .ingress_annotations
+            .insert("a".to_owned(), "b".to_owned());
+        assert_eq!(hash, spec_hash(&other));
+        // Unset fields.
+        other.spec.service.command = Some(vec!["nginx".to_owned()]);
+        assert_ne!(hash, spec_hash(&other));
+        other.spec.service.command = None;
+        assert_eq!(hash, spec_hash(&other));
+    }
+
+    #[test]
+    fn test_is_outdated() {
+        let mut config = Config::new("example.com".to_owned());
+        let eph = ephemeron();
+        let hash = spec_hash(&eph);
+        let mut pod = build_pod(&eph, &config);
+        assert!(!is_outdated(&pod, &hash));
+        assert!(is_outdated(&pod, "0000000000000000"));
+        // The defaults of the controller don't replace the existing Pods.
+        config
+            .node_selector
+            .insert("pool".to_owned(), "sandbox".to_owned());
+        assert!(!is_outdated(&pod, &spec_hash(&eph)));
+        // Older Pods without the hash are annotated instead.
+        pod.annotations_mut().remove(SPEC_HASH_ANNOTATION);
+        assert!(!is_outdated(&pod, &hash));
+    }
+
+    fn ports(additional_ports: &serde_json::Value) -> Ephemeron {
+        crate::resource::test_ephemeron(serde_json::json!({
+            "image": "nginx",
+            "port": 80,
+            "additionalPorts": additional_ports,
+        }))
+    }
+
+    #[test]
+    fn test_validate_ports() {
+        assert!(validate_ports(&ephemeron()).is_ok());
+        let valid = ports(&serde_json::json!([
+            {"name": "api", "port": 8080, "path": "/api"},
+            {"name": "dns", "port": 53, "protocol": "UDP"},
+            {"name": "dns-tcp", "port": 53},
+        ]));
+        assert!(validate_ports(&valid).is_ok());
+
+        for invalid in [
+            serde_json::json!([{"name": "main", "port": 8080}]),
+            serde_json::json!([{"name": "a", "port": 8080}, {"name": "a", "port": 8081}]),
+            serde_json::json!([{"name": "http", "port": 80}]),
+            serde_json::json!([{"name": "dns", "port": 53, "protocol": "UDP", "path": "/dns"}]),
+            serde_json::json!([{"name": "api", "port": 8080, "path": "api"}]),
+            serde_json::json!([{"name": "api", "port": 8080, "path": "/"}]),
+            serde_json::json!([
+                {"name": "a", "port": 8080, "path": "/api"},
+                {"name": "b", "port": 8081, "path": "/api"},
+            ]),
+        ] {
+            assert!(validate_ports(&ports(&invalid)).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_validate_volumes() {
+        let volumes = |vs: serde_json::Value| {
+            crate::resource::test_ephemeron(serde_json::json!({
+                "image": "nginx",
+                "port": 80,
+                "volumes": vs,
+            }))
+        };
+        assert!(validate_volumes(&ephemeron()).is_ok());
+        let valid = volumes(serde_json::json!([
+            {"name": "cache", "mountPath": "/cache"},
+            {"name": "tmp", "mountPath": "/tmp", "sizeLimit": "1Gi"},
+        ]));
+        assert!(validate_volumes(&valid).is_ok());
+
+        for invalid in [
+            serde_json::json!([
+                {"name": "cache", "mountPath": "/cache"},
+                {"name": "cache", "mountPath": "/tmp"},
+            ]),
+            serde_json::json!([{"name": "cache", "mountPath": "cache"}]),
+            serde_json::json!([
+                {"name": "cache", "mountPath": "/cache"},
+                {"name": "tmp", "mountPath": "/cache"},
+            ]),
+        ] {
+            assert!(
+                validate_volumes(&volumes(invalid.clone())).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_build_pod_spec_udp() {
+        let eph = crate::resource::test_ephemeron(serde_json::json!({
+            "image": "dns",
+            "port": 53,
+            "protocol": "UDP",
+            "additionalPorts": [{"name": "dns-tcp", "port": 53}],
+        }));
+        let spec = build_pod_spec(&eph, &Config::new("example.com".to_owned()));
+        let ports = spec.containers[0].ports.clone().unwrap();
+        assert_eq!(ports[0].container_port, 53);
+        assert_eq!(ports[0].protocol.as_deref(), Some("UDP"));
+        assert_eq!(ports[1].protocol, None);
+    }
+
+    #[test]
+    fn test_validate_init_containers() {
+        let init_containers = |names: &[&str]| {
+            let cs: Vec<_> = names
+                .iter()
+                .map(|n| serde_json::json!({"name": n, "image": "busybox"}))
+                .collect();
+            crate::resource::test_ephemeron(serde_json::json!({
+                "image": "nginx",
+                "port": 80,
+                "initContainers": cs,
+            }))
+        };
+        assert!(validate_init_containers(&ephemeron()).is_ok());
+        assert!(validate_init_containers(&init_containers(&["setup", "migrate"])).is_ok());
+        assert!(validate_init_containers(&init_containers(&["setup", "setup"])).is_err());
+        assert!(validate_init_containers(&init_containers(&["container"])).is_err());
+        assert!(validate(&init_containers(&["container"])).is_err());
+    }
+
+    #[test]
+    fn test_build_pod_spec_init_containers() {
+        let eph = crate::resource::test_ephemeron(serde_json::json!({
+            "image": "app",
+            "port": 8080,
+            "imagePullPolicy": "Always",
+            "volumes": [{"name": "data", "mountPath": "/data"}],
+            "initContainers": [
+                {"name": "fetch", "image": "curl", "command": ["curl", "-o", "/data/fixtures"]},
+                {
+                    "name": "seed",
+                    "image": "app",
+                    "env": [{"name": "MODE", "value": "seed"}],
+                    "workingDir": "/data",
+                },
+            ],
+        }));
+        let spec = build_pod_spec(&eph, &Config::new("example.com".to_owned()));
+        let init_containers = spec.init_containers.unwrap();
+        // In the order of the resource.
+        let names: Vec<_> = init_containers.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["fetch", "seed"]);
+        assert_eq!(init_containers[0].image.as_deref(), Some("curl"));
+        assert_eq!(
+            init_containers[0].command,
+            Some(vec![
+                "curl".to_owned(),
+                "-o".to_owned(),
+                "/data/fixtures".to_owned()
+            ])
+        );
+        assert_eq!(
+            init_containers[1].image_pull_policy.as_deref(),
+            Some("Always")
+        );
+        assert_eq!(init_containers[1].working_dir.as_deref(), Some("/data"));
+        let env = init_containers[1].env.clone().unwrap();
+        assert_eq!(env[0].name, "MODE");
+        assert_eq!(env[0].value.as_deref(), Some("seed"));
+        // The init containers and the container share the volume.
+        let volumes = spec.volumes.unwrap();
+        assert_eq!(volumes.len(), 1);
+        assert_eq!(volumes[0].name, "data");
+        for c in init_containers.iter().chain(&spec.containers) {
+            let mounts = c.volume_mounts.clone().unwrap();
+            assert_eq!(mounts[0].name, "data", "{}", c.name);
+            assert_eq!(mounts[0].mount_path, "/data", "{}", c.name);
+        }
+    }
+
+    #[test]
+    fn test_build_pod_spec_image_pull_secrets() {
+        let mut config = Config::new("example.com".to_owned());
+        let spec = build_pod_spec(&ephemeron(), &config);
+        assert_eq!(spec.image_pull_secrets, None);
+
+        config.image_pull_secrets = vec!["registry".to_owned(), "mirror".to_owned()];
+        let eph = crate::resource::test_ephemeron(serde_json::json!({
+            "image": "nginx",
+            "port": 80,
+            "imagePullSecrets": ["private", "registry"],
+        }));
+        let names = |spec: PodSpec| -> Vec<String> {
+            spec.image_pull_secrets
+                .unwrap()
+                .into_iter()
+                .map(|s| s.name.unwrap())
+                .collect()
+        };
+        // The defaults first, followed by the resource's without duplicates.
+        assert_eq!(
+            names(build_pod_spec(&eph, &config)),
+            ["registry", "mirror", "private"]
+        );
+        // The defaults apply without the resource's.
+        assert_eq!(
+            names(build_pod_spec(&ephemeron(), &config)),
+            ["registry", "mirror"]
+        );
+    }
+
+    #[test]
+    fn test_build_pod_spec_ports() {
+        let eph = ports(&serde_json::json!([
+            {"name": "metrics", "port": 9090},
+            {"name": "ws", "port": 8081, "path": "/ws"},
+        ]));
+        let spec = build_pod_spec(&eph, &Config::new("example.com".to_owned()));
+        let ports: Vec<_> = spec.containers[0]
+            .ports
+            .clone()
+            .unwrap()
+            .into_iter()
+            .map(|p| (p.name, p.container_port))
+            .collect();
+        // The primary port first, then the additional ones in order.
+        assert_eq!(
+            ports,
+            [
+                (None, 80),
+                (Some("metrics".to_owned()), 9090),
+                (Some("ws".to_owned()), 8081),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_pod_spec_placement() {
+        let mut config = Config::new("example.com".to_owned());
+        let spec = build_pod_spec(&ephemeron(), &config);
+        assert_eq!(spec.node_selector, None);
+        assert_eq!(spec.tolerations, None);
+
+        config
+            .node_selector
+            .insert("pool".to_owned(), "sandbox".to_owned());
+        config
+            .node_selector
+            .insert("disk".to_owned(), "hdd".to_owned());
+        let eph = crate::resource::test_ephemeron(serde_json::json!({
+            "image": "nginx",
+            "port": 80,
+            "arch": "arm64",
+            "nodeSelector": {"disk": "ssd", "kubernetes.io/arch": "amd64"},
+            "tolerations": [
+                {"key": "sandbox", "operator": "Exists", "effect": "NoSchedule"},
+            ],
+        }));
+        let spec = build_pod_spec(&eph, &config);
+        // The resource's override the defaults, and `arch` overrides both.
+        let expected: BTreeMap<_, _> = [
+            ("disk", "ssd"),
+            ("kubernetes.io/arch", "arm64"),
+            ("pool", "sandbox"),
+        ]
+        .iter()
+        .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+        .collect();
+        assert_eq!(spec.node_selector, Some(expected));
+        let tolerations = spec.tolerations.unwrap();
+        assert_eq!(tolerations.len(), 1);
+        assert_eq!(tolerations[0].key.as_deref(), Some("sandbox"));
+        assert_eq!(tolerations[0].operator.as_deref(), Some("Exists"));
+        assert_eq!(tolerations[0].effect.as_deref(), Some("NoSchedule"));
+    }
+
+    #[test]
+    fn test_build_pod_spec_security_context() {
+        let mut config = Config::new("example.com".to_owned());
+        let spec = build_pod_spec(&ephemeron(), &config);
+        assert_eq!(spec.security_context, None);
+        assert_eq!(spec.containers[0].security_context, None);
+
+        config.security_context = Some(SecurityContextSpec {
+            run_as_non_root: Some(true),
+            run_as_user: Some(1000),
+            run_as_group: Some(1000),
+            fs_group: Some(2000),
+            read_only_root_filesystem: Some(true),
+        });
+        let eph = crate::resource::test_ephemeron(serde_json::json!({
+            "image": "nginx",
+            "port": 80,
+            "initContainers": [{"name": "setup", "image": "busybox"}],
+        }));
+        // The default of the controller.
+        let spec = build_pod_spec(&eph, &config);
+        let pod = spec.security_context.unwrap();
+        assert_eq!(pod.run_as_non_root, Some(true));
+        assert_eq!(pod.run_as_user, Some(1000));
+        assert_eq!(pod.run_as_group, Some(1000));
+        assert_eq!(pod.fs_group, Some(2000));
+        for c in spec
+            .init_containers
+            .iter()
+            .flatten()
+            .chain(&spec.containers)
+        {
+            assert_eq!(
+                c.security_context
+                    .as_ref()
+                    .and_then(|sc| sc.read_only_root_filesystem),
+                Some(true),
+                "{}",
+                c.name
+            );
+        }
+
+        // The resource's replaces the default as a whole.
+        let eph = crate::resource::test_ephemeron(serde_json::json!({
+            "image": "nginx",
+            "port": 80,
+            "securityContext": {"runAsUser": 3000},
+        }));
+        let spec = build_pod_spec(&eph, &config);
+        let pod = spec.security_context.unwrap();
+        assert_eq!(pod.run_as_user, Some(3000));
+        assert_eq!(pod.run_as_non_root, None);
+        assert_eq!(pod.fs_group, None);
+        assert_eq!(spec.containers[0].security_context, None);
+    }
+
+    #[test]
+    fn test_build_pod_spec_probes() {
+        let spec = build_pod_spec(&ephemeron(), &Config::new("example.com".to_owned()));
+        assert_eq!(spec.containers[0].readiness_probe, None);
+        assert_eq!(spec.containers[0].startup_probe, None);
+        assert_eq!(spec.containers[0].liveness_probe, None);
+
+        let eph = crate::resource::test_ephemeron(serde_json::json!({
+            "image": "nginx",
+            "port": 8080,
+            "readinessProbe": {"path": "/ready", "periodSeconds": 5, "successThreshold": 2},
+            "startupProbe": {
+                "path": "/started",
+                "initialDelaySeconds": 10,
+                "failureThreshold": 30,
+                "successThreshold": 3,
+            },
+            "livenessProbe": {"path": "/healthz", "timeoutSeconds": 2},
+        }));
+        let spec = build_pod_spec(&eph, &Config::new("example.com".to_owned()));
+        let container = &spec.containers[0];
+        let path_and_port = |p: &Probe| {
+            let action = p.http_get.clone().unwrap();
+            (action.path.unwrap(), action.port)
+        };
+
+        let readiness = container.readiness_probe.as_ref().unwrap();
+        assert_eq!(
+            path_and_port(readiness),
+            ("/ready".to_owned(), IntOrString::Int(8080))
+        );
+        assert_eq!(readiness.period_seconds, Some(5));
+        assert_eq!(readiness.success_threshold, Some(2));
+
+        let startup = container.startup_probe.as_ref().unwrap();
+        assert_eq!(
+            path_and_port(startup),
+            ("/started".to_owned(), IntOrString::Int(8080))
+        );
+        assert_eq!(startup.initial_delay_seconds, Some(10));
+        assert_eq!(startup.failure_threshold, Some(30));
+        // Must be 1 for startup probes.
+        assert_eq!(startup.success_threshold, None);
+
+        let liveness = container.liveness_probe.as_ref().unwrap();
+        assert_eq!(
+            path_and_port(liveness),
+            ("/healthz".to_owned(), IntOrString::Int(8080))
+        );
+        assert_eq!(liveness.timeout_seconds, Some(2));
+        assert_eq!(liveness.period_seconds, None);
+    }
+
+    #[test]
+    fn test_paused_condition() {
+        use crate::resource::{EphemeronCondition, EphemeronPhase, EphemeronStatus};
+
+        let with_conditions = |conditions| {
+            let mut eph = ephemeron();
+            eph.spec.paused = true;
+            eph.status = Some(EphemeronStatus {
+                conditions,
+                ..EphemeronStatus::default()
+            });
+            eph
+        };
+        let running = with_conditions(vec![
+            EphemeronCondition::pod_ready(Some(true), "Running", None),
+            EphemeronCondition::available(Some(true), "EndpointsReady", None),
+        ]);
+        assert_eq!(paused_condition(&running), Some(PausedCondition::PodReady));
+        // `Available` after `PodReady`, regardless of the grace period.
+        let pod_paused = with_conditions(vec![
+            EphemeronCondition::pod_ready(Some(false), PAUSED, None),
+            EphemeronCondition::available(Some(true), "EndpointsReady", None),
+        ]);
+        assert_eq!(
+            paused_condition(&pod_paused),
+            Some(PausedCondition::Available)
+        );
+        let paused = with_conditions(vec![
+            EphemeronCondition::pod_ready(Some(false), PAUSED, None),
+            EphemeronCondition::available(Some(false), PAUSED, None),
+        ]);
+        assert_eq!(paused_condition(&paused), None);
+        assert_eq!(paused.conditions_phase(), EphemeronPhase::Pending);
+    }
 }
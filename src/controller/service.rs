@@ -5,14 +5,14 @@ use k8s_openapi::{
     apimachinery::pkg::util::intstr::IntOrString,
 };
 use kube::{
-    api::{ObjectMeta, PostParams},
+    api::{ObjectMeta, Patch, PatchParams, PostParams},
     error::ErrorResponse,
     runtime::controller::{Action, Context},
     Api, ResourceExt,
 };
 use thiserror::Error;
 
-use super::ContextData;
+use super::{Config, ContextData};
 use crate::Ephemeron;
 
 #[derive(Debug, Error)]
@@ -22,10 +22,16 @@ pub enum Error {
 
     #[error("failed to get service: {0}")]
     GetService(#[source] kube::Error),
+
+    #[error("failed to correct service: {0}")]
+    CorrectDrift(#[source] kube::Error),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+// The name of the primary port. Ports must be named when there's more than one.
+pub(super) const MAIN_PORT: &str = "main";
+
 #[tracing::instrument(skip(eph, ctx), level = "trace")]
 pub(super) async fn reconcile(
     eph: &Ephemeron,
@@ -34,17 +40,27 @@ pub(super) async fn reconcile(
     let name = eph.name();
     let client = ctx.get_ref().client.clone();
 
-    let svcs: Api<Service> = Api::namespaced(client.clone(), super::NS);
-    if svcs
-        .get_opt(&name)
+    let svcs: Api<Service> = Api::namespaced(
+        client.clone(),
+        eph.namespace_or(&ctx.get_ref().config.namespace),
+    );
+    let svc = build_service(eph, &ctx.get_ref().config);
+    if let Some(current) = svcs.get_opt(&name).await.map_err(Error::GetService)? {
+        if !has_drifted(&current, &svc) {
+            return Ok(None);
+        }
+        // Take back the fields changed by others.
+        tracing::debug!("Correcting Service");
+        svcs.patch(
+            &name,
+            &PatchParams::apply(super::FIELD_MANAGER).force(),
+            &Patch::Apply(&svc),
+        )
         .await
-        .map_err(Error::GetService)?
-        .is_some()
-    {
-        Ok(None)
+        .map_err(Error::CorrectDrift)?;
+        Ok(Some(Action::await_change()))
     } else {
         tracing::debug!("Creating Service");
-        let svc = build_service(eph);
         match svcs.create(&PostParams::default(), &svc).await {
             Ok(_) => Ok(Some(Action::await_change())),
             Err(kube::Error::Api(ErrorResponse { code: 409, .. })) => {
@@ -56,23 +72,55 @@ pub(super) async fn reconcile(
     }
 }
 
-fn build_service(eph: &Ephemeron) -> Service {
+// `true` if the managed fields of `current` differ from `desired`.
+// Fields defaulted by the server (e.g., `nodePort`) are ignored.
+fn has_drifted(current: &Service, desired: &Service) -> bool {
+    let managed = |svc: &Service| {
+        let spec = svc.spec.clone().unwrap_or_default();
+        let ports: Vec<_> = spec
+            .ports
+            .unwrap_or_default()
+            .into_iter()
+            .map(|p| (p.name, p.port, p.protocol, p.target_port))
+            .collect();
+        (spec.type_, ports, spec.selector)
+    };
+    managed(current) != managed(desired)
+}
+
+fn build_service(eph: &Ephemeron, config: &Config) -> Service {
     let name = eph.name();
+    let mut labels = eph.spec.service.service_labels.clone();
+    labels.append(&mut super::make_labels(eph, config));
     Service {
         metadata: ObjectMeta {
             name: Some(name.clone()),
-            namespace: Some(super::NS.into()),
+            namespace: Some(eph.namespace_or(&config.namespace).to_owned()),
             owner_references: Some(vec![super::to_owner_reference(eph)]),
-            labels: Some(super::make_common_labels(&name)),
+            labels: Some(labels),
+            annotations: Some(eph.spec.service.service_annotations.clone()),
             ..ObjectMeta::default()
         },
         spec: Some(ServiceSpec {
-            type_: Some("ClusterIP".into()),
-            ports: Some(vec![ServicePort {
-                port: eph.spec.service.port,
-                target_port: Some(IntOrString::Int(eph.spec.service.port)),
-                ..ServicePort::default()
-            }]),
+            type_: Some(eph.service_type().to_owned()),
+            ports: Some(
+                std::iter::once((MAIN_PORT, eph.spec.service.port, eph.protocol()))
+                    .chain(
+                        eph.spec
+                            .service
+                            .additional_ports
+                            .iter()
+                            .map(|p| (p.name.as_str(), p.port, p.protocol())),
+                    )
+                    .map(|(name, port, protocol)| ServicePort {
+                        name: Some(name.to_owned()),
+                        port,
+                        protocol: Some(protocol.to_owned()),
+                        target_port: Some(IntOrString::Int(port)),
+                        ..ServicePort::default()
+                    })
+                    .collect(),
+            ),
             selector: Some(BTreeMap::from([(
                 "app.kubernetes.io/name".to_owned(),
                 name,
@@ -82,3 +130,142 @@ fn build_service(eph: &Ephemeron) -> Service {
         ..Service::default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service() -> Service {
+        let eph = crate::resource::test_ephemeron(serde_json::json!({
+            "image": "nginx",
+            "port": 80,
+            "serviceType": "NodePort",
+        }));
+        build_service(&eph, &Config::new("example.com".to_owned()))
+    }
+
+    #[test]
+    fn test_has_drifted() {
+        let desired = service();
+        assert!(!has_drifted(&desired, &desired));
+
+        let mut current = desired.clone();
+        if let Some(ports) = current.spec.as_mut().and_then(|s| s.ports.as_mut()) {
+            ports[0].port = 8080;
+        }
+        assert!(has_drifted(&current, &desired));
+
+        let mut current = desired.clone();
+        current.spec.as_mut().unwrap().type_ = Some("LoadBalancer".to_owned());
+        assert!(has_drifted(&current, &desired));
+    }
+
+    #[test]
+    fn test_has_drifted_ignores_defaulted() {
+        let desired = service();
+        let mut current = desired.clone();
+        let spec = current.spec.as_mut().unwrap();
+        spec.cluster_ip = Some("10.0.0.1".to_owned());
+        spec.session_affinity = Some("None".to_owned());
+        if let Some(ports) = spec.ports.as_mut() {
+            ports[0].node_port = Some(30080);
+        }
+        assert!(!has_drifted(&current, &desired));
+    }
+
+    fn service_type(service_type: Option<&str>) -> ServiceSpec {
+        let eph = crate::resource::test_ephemeron(serde_json::json!({
+            "image": "nginx",
+            "port": 80,
+            "serviceType": service_type,
+        }));
+        build_service(&eph, &Config::new("example.com".to_owned()))
+            .spec
+            .unwrap()
+    }
+
+    #[test]
+    fn test_build_service_namespace() {
+        let mut config = Config::new("example.com".to_owned());
+        config.namespace = "sandboxes".to_owned();
+        let mut eph =
+            crate::resource::test_ephemeron(serde_json::json!({"image": "nginx", "port": 80}));
+        assert_eq!(
+            build_service(&eph, &config).namespace().as_deref(),
+            Some("sandboxes")
+        );
+        // Created in the namespace of the app.
+        eph.annotations_mut().insert(
+            crate::resource::NAMESPACE_ANNOTATION.to_owned(),
+            "ephemeron-example".to_owned(),
+        );
+        assert_eq!(
+            build_service(&eph, &config).namespace().as_deref(),
+            Some("ephemeron-example")
+        );
+    }
+
+    #[test]
+    fn test_build_service_types() {
+        for (requested, expected) in [
+            (None, "ClusterIP"),
+            (Some("ClusterIP"), "ClusterIP"),
+            (Some("NodePort"), "NodePort"),
+            (Some("LoadBalancer"), "LoadBalancer"),
+        ] {
+            let spec = service_type(requested);
+            assert_eq!(spec.type_.as_deref(), Some(expected));
+            let ports = spec.ports.unwrap();
+            assert_eq!(ports.len(), 1);
+            assert_eq!(ports[0].port, 80);
+            assert_eq!(ports[0].target_port, Some(IntOrString::Int(80)));
+            // Assigned by the server.
+            assert_eq!(ports[0].node_port, None);
+            assert_eq!(
+                spec.selector.unwrap()["app.kubernetes.io/name"],
+                "test".to_owned()
+            );
+        }
+    }
+
+    #[test]
+    fn test_build_service_udp() {
+        let eph = crate::resource::test_ephemeron(serde_json::json!({
+            "image": "dns",
+            "port": 53,
+            "protocol": "UDP",
+            "additionalPorts": [{"name": "dns-tcp", "port": 53}],
+        }));
+        let svc = build_service(&eph, &Config::new("example.com".to_owned()));
+        let ports: Vec<_> = svc
+            .spec
+            .unwrap()
+            .ports
+            .unwrap()
+            .into_iter()
+            .map(|p| (p.name.unwrap(), p.port, p.protocol.unwrap()))
+            .collect();
+        assert_eq!(
+            ports,
+            [
+                (MAIN_PORT.to_owned(), 53, "UDP".to_owned()),
+                ("dns-tcp".to_owned(), 53, "TCP".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_service_labels() {
+        let eph = crate::resource::test_ephemeron(serde_json::json!({
+            "image": "nginx",
+            "port": 80,
+            "serviceLabels": {"team": "a", "app.kubernetes.io/name": "other"},
+            "serviceAnnotations": {"lb.example.com/internal": "true"},
+        }));
+        let svc = build_service(&eph, &Config::new("example.com".to_owned()));
+        // The managed labels take precedence.
+        assert_eq!(svc.labels()["app.kubernetes.io/name"], "test");
+        assert_eq!(svc.labels()["team"], "a");
+        assert_eq!(svc.annotations()["lb.example.com/internal"], "true");
+    }
+}
@@ -5,7 +5,11 @@ pub mod api;
 mod controller;
 mod resource;
 
-pub use controller::run;
+pub use controller::{
+    metrics, run, Config as ControllerConfig, ConfigError as ControllerConfigError,
+    Features as ControllerFeatures, ProbeRetry,
+};
 pub use resource::{
-    Ephemeron, EphemeronCondition, EphemeronService, EphemeronSpec, EphemeronStatus,
+    Ephemeron, EphemeronCondition, EphemeronPhase, EphemeronService, EphemeronSpec,
+    EphemeronStatus, SecurityContextSpec,
 };
@@ -12,6 +12,69 @@ use serde::{Deserialize, Serialize};
 
 mod schemas;
 
+/// Annotation for the subject that created the resource. Used for access control.
+pub(crate) const CREATED_BY: &str = "ephemerons.qualified.io/created-by";
+/// Label for the group of the subject that created the resource. Also added to the Pod.
+pub(crate) const GROUP_LABEL: &str = "ephemerons.qualified.io/group";
+/// Annotation for the name of the preset the resource was created with.
+pub(crate) const PRESET_ANNOTATION: &str = "ephemerons.qualified.io/preset";
+/// Prefix of the annotations for the metadata supplied by the client on create.
+pub(crate) const METADATA_PREFIX: &str = "meta.ephemerons.qualified.io/";
+/// Label for the hash of the subject that created the resource, so the ones it created can be selected.
+/// Subjects can't be label values. Also check `CREATED_BY` because different subjects can have the same hash.
+pub(crate) const CREATOR_LABEL: &str = "ephemerons.qualified.io/creator";
+/// Label for the app of the subject that created the resource.
+pub(crate) const APP_LABEL: &str = "ephemerons.qualified.io/app";
+/// Annotation for the namespace to create the children in. The controller's namespace if missing.
+pub(crate) const NAMESPACE_ANNOTATION: &str = "ephemerons.qualified.io/namespace";
+
+/// Length of the generated ids (`xid`).
+pub(crate) const ID_LEN: usize = 20;
+
+/// The value of `CREATOR_LABEL` for `sub`.
+pub(crate) fn creator_label(sub: &str) -> String {
+    hash(sub.as_bytes())
+}
+
+/// FNV-1a hash of `bytes` in hex. Not for security, only to make short and valid label values.
+pub(crate) fn hash(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325_u64, |h, &b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// The namespace for the services created by `app`. `None` if it'd be an invalid name.
+pub(crate) fn app_namespace(app: &str) -> Option<String> {
+    let ns = format!("ephemeron-{}", app);
+    (!ns.contains('.') && is_valid_host(&ns)).then(|| ns)
+}
+
+/// Label values are 63 characters or less of alphanumerics with `-`, `_`, and `.` between.
+pub(crate) fn is_label_value(s: &str) -> bool {
+    !s.is_empty()
+        && s.len() <= 63
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        && s.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && s.ends_with(|c: char| c.is_ascii_alphanumeric())
+}
+
+/// `true` if `host` is a valid DNS name within the limits.
+/// Labels are 63 characters or less of alphanumerics and `-`, and the name is 253 characters or less.
+pub(crate) fn is_valid_host(host: &str) -> bool {
+    host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+        })
+}
+
 #[derive(CustomResource, Deserialize, Serialize, Debug, PartialEq, Clone, JsonSchema)]
 #[kube(
     group = "qualified.io",
@@ -20,7 +83,11 @@ mod schemas;
     plural = "ephemerons",
     shortname = "eph",
     shortname = "ephs",
-    status = "EphemeronStatus"
+    status = "EphemeronStatus",
+    printcolumn = r#"{"name": "Host", "type": "string", "jsonPath": ".status.host"}"#,
+    printcolumn = r#"{"name": "Available", "type": "string", "jsonPath": ".status.conditions[?(@.type==\"Available\")].status"}"#,
+    printcolumn = r#"{"name": "Expires", "type": "date", "jsonPath": ".spec.expirationTime"}"#,
+    printcolumn = r#"{"name": "Age", "type": "date", "jsonPath": ".metadata.creationTimestamp"}"#
 )]
 #[serde(rename_all = "camelCase")]
 pub struct EphemeronSpec {
@@ -28,6 +95,12 @@ pub struct EphemeronSpec {
     pub expiration_time: DateTime<Utc>,
     /// The service to create.
     pub service: EphemeronService,
+    /// Delete the Pod to stop the service while keeping the rest. The expiration time is unchanged.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub paused: bool,
+    /// URL to `POST` `{id, host, tls, expirationTime}` to once the service becomes available.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_url: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug, PartialEq, Clone, JsonSchema)]
@@ -35,6 +108,8 @@ pub struct EphemeronSpec {
 pub struct EphemeronService {
     /// The image to use.
     pub image: String,
+    /// The image to use instead if pulling `image` kept failing.
+    pub fallback_image: Option<String>,
     /// Optionally specify the command to use.
     pub command: Option<Vec<String>>,
     /// The directory to run command in.
@@ -42,18 +117,58 @@ pub struct EphemeronService {
     /// The port to use.
     #[schemars(schema_with = "schemas::port")]
     pub port: i32,
+    /// The protocol of `port`. One of `TCP`, `UDP`, `SCTP`. Defaults to `TCP`.
+    /// The Ingress is only created for `TCP`.
+    #[schemars(schema_with = "schemas::protocol")]
+    pub protocol: Option<String>,
+    /// More ports to expose on the Service. The Ingress routes to `port` unless the path is set.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub additional_ports: Vec<NamedPort>,
+    /// The type of the Service. One of `ClusterIP`, `NodePort`, `LoadBalancer`. Defaults to `ClusterIP`.
+    /// With `LoadBalancer`, `host` is the address assigned to the load balancer.
+    #[schemars(schema_with = "schemas::service_type")]
+    pub service_type: Option<String>,
     /// The name of the TLS secret.
     pub tls_secret_name: Option<String>,
+    /// The class of the Ingress. Defaults to the one of the controller, or the default class of the cluster.
+    pub ingress_class_name: Option<String>,
     /// Ingress annotations.
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub ingress_annotations: BTreeMap<String, String>,
+    /// Additional labels to add to the Ingress. The managed labels take precedence.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub ingress_labels: BTreeMap<String, String>,
+    /// Service annotations. e.g., to configure the load balancer.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub service_annotations: BTreeMap<String, String>,
+    /// Additional labels to add to the Service. The managed labels take precedence.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub service_labels: BTreeMap<String, String>,
 
     /// Probe to tell when the service is ready to accept traffic.
     pub readiness_probe: Option<HttpGetProbe>,
 
-    /// Image pull policy. One of Always, Never, IfNotPresent.
+    /// More probes to tell when the service is ready to accept traffic.
+    /// Only the first probe is used by the container. The controller checks all of them from outside
+    /// before marking the service `Available`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub additional_readiness_probes: Vec<HttpGetProbe>,
+
+    /// Probe to tell when the container has started. The other probes wait for it to succeed.
+    /// The container is restarted if it fails, so slow starting services don't get killed by `livenessProbe`.
+    /// `successThreshold` is ignored.
+    pub startup_probe: Option<HttpGetProbe>,
+
+    /// Probe to tell when the container should be restarted. `successThreshold` is ignored.
+    pub liveness_probe: Option<HttpGetProbe>,
+
+    /// Image pull policy. One of `Always`, `Never`, `IfNotPresent`.
     pub image_pull_policy: Option<String>,
 
+    /// Names of the Secrets in the namespace to pull the images with.
+    /// Added to the default ones of the controller.
+    pub image_pull_secrets: Option<Vec<String>>,
+
     /// Compute Resources required by this container.
     pub resources: Option<k8s_openapi::api::core::v1::ResourceRequirements>,
 
@@ -63,6 +178,128 @@ pub struct EphemeronService {
     /// Additional labels to add to the Pod.
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub pod_labels: BTreeMap<String, String>,
+
+    /// The CPU architecture of the image. One of `amd64`, `arm64`.
+    /// The Pod is scheduled to the nodes with the matching `kubernetes.io/arch`.
+    #[schemars(schema_with = "schemas::arch")]
+    pub arch: Option<String>,
+
+    /// Labels of the nodes to schedule the Pod to. Merged over the default of the controller.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub node_selector: BTreeMap<String, String>,
+
+    /// Allow scheduling the Pod to the nodes with the matching taints.
+    pub tolerations: Option<Vec<k8s_openapi::api::core::v1::Toleration>>,
+
+    /// Projected service account token to mount with a custom audience.
+    /// Distinct from the default service account token.
+    pub service_account_token: Option<ServiceAccountToken>,
+
+    /// Allocate a buffer for stdin in the container. Required to attach. Defaults to `false`.
+    pub stdin: Option<bool>,
+
+    /// Allocate a TTY for the container. Requires `stdin` to be useful. Defaults to `false`.
+    pub tty: Option<bool>,
+
+    /// Isolate the Pod for running untrusted code.
+    /// The default service account token is not mounted, and the network traffic is only allowed
+    /// from and to the ingress controller.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub network_isolation: bool,
+
+    /// Containers to run to completion in order before the container starts.
+    pub init_containers: Option<Vec<InitContainerSpec>>,
+
+    /// Writable scratch space to mount in the container. Deleted with the Pod.
+    pub volumes: Option<Vec<EmptyDirVolume>>,
+
+    /// Users and permissions to run the containers with. Defaults to the one of the controller.
+    pub security_context: Option<SecurityContextSpec>,
+}
+
+/// Subset of `k8s_openapi::api::core::v1::PodSecurityContext` and `SecurityContext`.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SecurityContextSpec {
+    /// Refuse to start the containers if they run as root.
+    pub run_as_non_root: Option<bool>,
+    /// The UID to run the containers as. Defaults to the user of the image.
+    pub run_as_user: Option<i64>,
+    /// The GID to run the containers as. Defaults to the group of the image.
+    pub run_as_group: Option<i64>,
+    /// The group owning the volumes.
+    pub fs_group: Option<i64>,
+    /// Mount the root filesystem of the containers as read-only. Use `volumes` for writable paths.
+    pub read_only_root_filesystem: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NamedPort {
+    /// The name of the port. Must be unique, and not `main`.
+    #[schemars(regex(pattern = r"^[a-z0-9]([-a-z0-9]*[a-z0-9])?$"), length(max = 15))]
+    pub name: String,
+    /// The port number. Must be different from the others with the same protocol.
+    #[schemars(schema_with = "schemas::port")]
+    pub port: i32,
+    /// The protocol. One of `TCP`, `UDP`, `SCTP`. Defaults to `TCP`.
+    #[schemars(schema_with = "schemas::protocol")]
+    pub protocol: Option<String>,
+    /// Route requests with this path prefix to this port through the Ingress. Only for `TCP`.
+    /// Must start with `/`, and be unique. `/` is used by `port`.
+    pub path: Option<String>,
+}
+
+impl NamedPort {
+    pub(crate) fn protocol(&self) -> &str {
+        self.protocol.as_deref().unwrap_or("TCP")
+    }
+}
+
+/// `emptyDir` volume mounted in the container.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EmptyDirVolume {
+    /// The name of the volume. Must be unique.
+    #[schemars(regex(pattern = r"^[a-z0-9]([-a-z0-9]*[a-z0-9])?$"), length(max = 63))]
+    pub name: String,
+    /// The absolute path to mount the volume at.
+    pub mount_path: String,
+    /// The maximum size of the volume. e.g., `1Gi`. Unlimited by default.
+    pub size_limit: Option<String>,
+}
+
+/// Subset of `k8s_openapi::api::core::v1::Container` for init containers.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InitContainerSpec {
+    /// The name of the container. Must be unique, and not `container`.
+    #[schemars(regex(pattern = r"^[a-z0-9]([-a-z0-9]*[a-z0-9])?$"), length(max = 63))]
+    pub name: String,
+    /// The image to use.
+    pub image: String,
+    /// Optionally specify the command to use.
+    pub command: Option<Vec<String>>,
+    /// List of environment variables to set in the container.
+    pub env: Option<Vec<EnvVar>>,
+    /// The directory to run command in.
+    pub working_dir: Option<String>,
+    /// Compute Resources required by this container.
+    pub resources: Option<k8s_openapi::api::core::v1::ResourceRequirements>,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceAccountToken {
+    /// The intended audience of the token.
+    pub audience: String,
+
+    /// The requested duration of validity of the token in seconds. Defaults to 1 hour. Minimum value is 600.
+    #[schemars(range(min = 600))]
+    pub expiration_seconds: Option<i64>,
+
+    /// The directory to mount the token in. The token is written to `token` file in it.
+    pub mount_path: String,
 }
 
 /// `k8s_openapi::api::core::v1::EnvVar` minus `value_from`.
@@ -88,9 +325,24 @@ pub struct HttpGetProbe {
 
     /// Number of seconds after which the probe times out. Defaults to 1 second. Minimum value is 1.
     pub timeout_seconds: Option<i32>,
+
+    /// Minimum consecutive successes for the probe to be considered successful after having failed.
+    /// Defaults to 1. Minimum value is 1.
+    #[schemars(range(min = 1))]
+    pub success_threshold: Option<i32>,
+
+    /// Minimum consecutive failures for the probe to be considered failed after having succeeded.
+    /// Defaults to 3. Minimum value is 1.
+    #[schemars(range(min = 1))]
+    pub failure_threshold: Option<i32>,
+
+    /// The scheme to use when the controller checks the probe from outside. One of `HTTP`, `HTTPS`.
+    /// Defaults to `HTTPS` if TLS is configured, `HTTP` otherwise. The container is always probed with `HTTP`.
+    #[schemars(schema_with = "schemas::probe_scheme")]
+    pub scheme: Option<String>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, JsonSchema)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct EphemeronStatus {
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
@@ -98,8 +350,78 @@ pub struct EphemeronStatus {
     pub conditions: Vec<EphemeronCondition>,
 
     /// The last reconciled generation.
+    #[serde(skip_serializing_if = "Option::is_none")]
     #[schemars(schema_with = "schemas::observed_generation")]
     pub observed_generation: Option<i64>,
+
+    /// `true` if the `Service` has ready endpoints, i.e., reachable from inside the cluster.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoints_ready: Option<bool>,
+
+    /// `true` if the readiness probes succeeded from outside of the cluster.
+    /// Not set if the service doesn't have readiness probes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub externally_reachable: Option<bool>,
+
+    /// The host to access the service at. Same as `host` annotation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+
+    /// Summary of the conditions. Updated with them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phase: Option<EphemeronPhase>,
+}
+
+/// Summary of the conditions, so consumers don't have to interpret them.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Copy, JsonSchema)]
+pub enum EphemeronPhase {
+    /// Waiting for the Pod to be created, e.g., creating, replacing, or paused.
+    Pending,
+    /// The Pod exists, but the service is not available yet.
+    Starting,
+    /// The service is available.
+    Available,
+    /// `Expiring` is `True`.
+    Expiring,
+    /// `Failed` is `True`. The service won't be available.
+    Failed,
+    /// The status of the Pod is unknown.
+    Unknown,
+}
+
+impl EphemeronPhase {
+    fn from_conditions<'a, I>(conditions: I) -> Self
+    where
+        I: IntoIterator<Item = &'a EphemeronCondition>,
+    {
+        let (mut pod_ready, mut available, mut failed, mut expiring) = (None, None, None, None);
+        for c in conditions {
+            match c {
+                EphemeronCondition::PodReady { .. } => pod_ready = Some(c),
+                EphemeronCondition::Available { .. } => available = Some(c),
+                EphemeronCondition::Failed { .. } => failed = Some(c),
+                EphemeronCondition::Expiring { .. } => expiring = Some(c),
+            }
+        }
+        let is_true =
+            |c: Option<&EphemeronCondition>| c.map_or(false, |c| c.status() == Some(true));
+        if is_true(failed) {
+            Self::Failed
+        } else if is_true(expiring) {
+            Self::Expiring
+        } else if is_true(available) {
+            Self::Available
+        } else {
+            match pod_ready.map(|c| (c.status(), c.reason())) {
+                // The reasons of the Pod step while there's no Pod.
+                None | Some((Some(false), Some("PodCreating" | "PodReplacing" | "Paused"))) => {
+                    Self::Pending
+                }
+                Some((None, _)) => Self::Unknown,
+                Some(_) => Self::Starting,
+            }
+        }
+    }
 }
 
 // Helper methods for conditions.
@@ -130,6 +452,66 @@ impl Ephemeron {
         .is_some()
     }
 
+    /// The `Failed` condition if it's `True`.
+    pub(crate) fn failure(&self) -> Option<&EphemeronCondition> {
+        self.find_condition(|c| {
+            matches!(
+                c,
+                EphemeronCondition::Failed {
+                    status: Some(true),
+                    ..
+                }
+            )
+        })
+    }
+
+    /// `true` if the `Expiring` condition is `True`.
+    pub(crate) fn is_expiring(&self) -> bool {
+        self.find_condition(|c| {
+            matches!(
+                c,
+                EphemeronCondition::Expiring {
+                    status: Some(true),
+                    ..
+                }
+            )
+        })
+        .is_some()
+    }
+
+    /// The reason of the `Available` condition.
+    pub(crate) fn available_reason(&self) -> Option<&str> {
+        self.find_condition(|c| matches!(c, EphemeronCondition::Available { .. }))
+            .and_then(EphemeronCondition::reason)
+    }
+
+    pub(crate) fn endpoints_ready(&self) -> Option<bool> {
+        self.status.as_ref().and_then(|s| s.endpoints_ready)
+    }
+
+    pub(crate) fn externally_reachable(&self) -> Option<bool> {
+        self.status.as_ref().and_then(|s| s.externally_reachable)
+    }
+
+    pub(crate) fn status_host(&self) -> Option<&str> {
+        self.status.as_ref().and_then(|s| s.host.as_deref())
+    }
+
+    /// The phase summarizing the current conditions.
+    pub(crate) fn conditions_phase(&self) -> EphemeronPhase {
+        EphemeronPhase::from_conditions(self.status.iter().flat_map(|s| s.conditions.iter()))
+    }
+
+    pub(crate) fn phase(&self) -> Option<EphemeronPhase> {
+        self.status.as_ref().and_then(|s| s.phase)
+    }
+
+    /// The reason of the `PodReady` condition.
+    pub(crate) fn pod_ready_reason(&self) -> Option<&str> {
+        self.find_condition(|c| matches!(c, EphemeronCondition::PodReady { .. }))
+            .and_then(EphemeronCondition::reason)
+    }
+
     fn find_condition<F>(&self, mut f: F) -> Option<&EphemeronCondition>
     where
         F: FnMut(&EphemeronCondition) -> bool,
@@ -139,8 +521,78 @@ impl Ephemeron {
             .and_then(|s| s.conditions.iter().find(|&c| f(c)))
     }
 
+    /// All the readiness probes. `readiness_probe` first.
+    pub(crate) fn readiness_probes(&self) -> impl Iterator<Item = &HttpGetProbe> {
+        self.spec
+            .service
+            .readiness_probe
+            .iter()
+            .chain(self.spec.service.additional_readiness_probes.iter())
+    }
+
+    // Load balancers are in front of the Service without the TLS termination of the Ingress.
     pub(crate) fn has_tls(&self) -> bool {
-        self.spec.service.tls_secret_name.is_some()
+        self.spec.service.tls_secret_name.is_some() && !self.is_load_balancer()
+    }
+
+    pub(crate) fn service_type(&self) -> &str {
+        self.spec
+            .service
+            .service_type
+            .as_deref()
+            .unwrap_or("ClusterIP")
+    }
+
+    /// The protocol of `port`.
+    pub(crate) fn protocol(&self) -> &str {
+        self.spec.service.protocol.as_deref().unwrap_or("TCP")
+    }
+
+    /// `true` if `port` can be routed through the Ingress.
+    pub(crate) fn is_http_routable(&self) -> bool {
+        self.protocol() == "TCP"
+    }
+
+    pub(crate) fn is_load_balancer(&self) -> bool {
+        self.service_type() == "LoadBalancer"
+    }
+
+    /// The group of the creator. Older resources only have it on the Pod labels.
+    pub(crate) fn group(&self) -> Option<&str> {
+        self.metadata
+            .labels
+            .as_ref()
+            .and_then(|ls| ls.get(GROUP_LABEL))
+            .or_else(|| self.spec.service.pod_labels.get(GROUP_LABEL))
+            .map(String::as_str)
+    }
+
+    /// The namespace of the children. `default` unless set on create.
+    pub(crate) fn namespace_or<'a>(&'a self, default: &'a str) -> &'a str {
+        self.metadata
+            .annotations
+            .as_ref()
+            .and_then(|an| an.get(NAMESPACE_ANNOTATION))
+            .map_or(default, String::as_str)
+    }
+
+    /// The metadata supplied by the client on create.
+    pub(crate) fn client_metadata(&self) -> BTreeMap<&str, &str> {
+        self.metadata
+            .annotations
+            .iter()
+            .flatten()
+            .filter_map(|(k, v)| Some((k.strip_prefix(METADATA_PREFIX)?, v.as_str())))
+            .collect()
+    }
+
+    /// The name of the preset the resource was created with.
+    pub(crate) fn preset(&self) -> Option<&str> {
+        self.metadata
+            .annotations
+            .as_ref()
+            .and_then(|an| an.get(PRESET_ANNOTATION))
+            .map(String::as_str)
     }
 }
 
@@ -159,6 +611,13 @@ pub enum EphemeronCondition {
         // TODO Use the time from Pod?
         /// Last time the condition transitioned from one status to another.
         last_transition_time: DateTime<Utc>,
+        /// Machine-readable reason for the condition's last transition in `CamelCase`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+
+        /// Human-readable message indicating details about the transition.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
     },
 
     #[serde(rename_all = "camelCase")]
@@ -172,32 +631,136 @@ pub enum EphemeronCondition {
 
         /// Last time the condition transitioned from one status to another.
         last_transition_time: DateTime<Utc>,
+        /// Machine-readable reason for the condition's last transition in `CamelCase`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+
+        /// Human-readable message indicating details about the transition.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    Failed {
+        /// Status of the condition. Maps to String enum: Unknown, True, False.
+        #[serde(
+            serialize_with = "condition_status_ser",
+            deserialize_with = "condition_status_de"
+        )]
+        status: Option<bool>,
+
+        /// Last time the condition transitioned from one status to another.
+        last_transition_time: DateTime<Utc>,
+
+        /// Machine-readable reason for the condition's last transition in `CamelCase`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+
+        /// Human-readable message indicating details about the transition.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    Expiring {
+        /// Status of the condition. Maps to String enum: Unknown, True, False.
+        #[serde(
+            serialize_with = "condition_status_ser",
+            deserialize_with = "condition_status_de"
+        )]
+        status: Option<bool>,
+
+        /// Last time the condition transitioned from one status to another.
+        last_transition_time: DateTime<Utc>,
+
+        /// Machine-readable reason for the condition's last transition in `CamelCase`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+
+        /// Human-readable message indicating details about the transition.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
     },
 }
 
 // The names of managers to be used to update the field in controller.
 const POD_READY_MANAGER: &str = "ephemeron-podready";
 const AVAILABLE_MANAGER: &str = "ephemeron-available";
+const FAILED_MANAGER: &str = "ephemeron-failed";
+const EXPIRING_MANAGER: &str = "ephemeron-expiring";
+pub(crate) const REACHABILITY_MANAGER: &str = "ephemeron-reachability";
+pub(crate) const PHASE_MANAGER: &str = "ephemeron-phase";
 
 impl EphemeronCondition {
     pub(crate) fn manager(&self) -> &str {
         match self {
             EphemeronCondition::PodReady { .. } => POD_READY_MANAGER,
             EphemeronCondition::Available { .. } => AVAILABLE_MANAGER,
+            EphemeronCondition::Failed { .. } => FAILED_MANAGER,
+            EphemeronCondition::Expiring { .. } => EXPIRING_MANAGER,
         }
     }
 
-    pub(crate) fn pod_ready(status: Option<bool>) -> Self {
+    pub(crate) fn pod_ready(status: Option<bool>, reason: &str, message: Option<String>) -> Self {
         Self::PodReady {
             status,
             last_transition_time: Utc::now(),
+            reason: Some(reason.to_owned()),
+            message,
         }
     }
 
-    pub(crate) fn available(status: Option<bool>) -> Self {
+    pub(crate) fn available(status: Option<bool>, reason: &str, message: Option<String>) -> Self {
         Self::Available {
             status,
             last_transition_time: Utc::now(),
+            reason: Some(reason.to_owned()),
+            message,
+        }
+    }
+
+    pub(crate) fn failed(status: Option<bool>, reason: &str, message: Option<String>) -> Self {
+        Self::Failed {
+            status,
+            last_transition_time: Utc::now(),
+            reason: Some(reason.to_owned()),
+            message,
+        }
+    }
+
+    pub(crate) fn expiring(status: Option<bool>, reason: &str, message: Option<String>) -> Self {
+        Self::Expiring {
+            status,
+            last_transition_time: Utc::now(),
+            reason: Some(reason.to_owned()),
+            message,
+        }
+    }
+
+    pub(crate) fn status(&self) -> Option<bool> {
+        match self {
+            EphemeronCondition::PodReady { status, .. }
+            | EphemeronCondition::Available { status, .. }
+            | EphemeronCondition::Failed { status, .. }
+            | EphemeronCondition::Expiring { status, .. } => *status,
+        }
+    }
+
+    pub(crate) fn reason(&self) -> Option<&str> {
+        match self {
+            EphemeronCondition::PodReady { reason, .. }
+            | EphemeronCondition::Available { reason, .. }
+            | EphemeronCondition::Failed { reason, .. }
+            | EphemeronCondition::Expiring { reason, .. } => reason.as_deref(),
+        }
+    }
+
+    pub(crate) fn message(&self) -> Option<&str> {
+        match self {
+            EphemeronCondition::PodReady { message, .. }
+            | EphemeronCondition::Available { message, .. }
+            | EphemeronCondition::Failed { message, .. }
+            | EphemeronCondition::Expiring { message, .. } => message.as_deref(),
         }
     }
 }
@@ -217,7 +780,7 @@ where
     }
 }
 
-#[allow(clippy::trivially_copy_pass_by_ref)]
+#[allow(clippy::trivially_copy_pass_by_ref, clippy::ref_option)]
 fn condition_status_ser<S>(status: &Option<bool>, s: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
@@ -228,3 +791,194 @@ where
         None => "Unknown",
     })
 }
+
+/// An `Ephemeron` named `test` with the `service` spec to use in tests.
+#[cfg(test)]
+#[allow(clippy::needless_pass_by_value)]
+pub(crate) fn test_ephemeron(service: serde_json::Value) -> Ephemeron {
+    serde_json::from_value(serde_json::json!({
+        "apiVersion": "qualified.io/v1alpha1",
+        "kind": "Ephemeron",
+        "metadata": { "name": "test", "uid": "test-uid" },
+        "spec": {
+            "expirationTime": "2030-01-01T00:00:00Z",
+            "service": service,
+        },
+    }))
+    .expect("valid ephemeron")
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+    use kube::CustomResourceExt;
+
+    use super::*;
+
+    #[test]
+    fn test_is_label_value() {
+        assert!(is_label_value("nginx"));
+        assert!(is_label_value("node-16.x_lts"));
+        assert!(!is_label_value(""));
+        assert!(!is_label_value("-nginx"));
+        assert!(!is_label_value("nginx."));
+        assert!(!is_label_value("node/16"));
+        assert!(!is_label_value(&"x".repeat(64)));
+    }
+
+    // The CRD as applied from the output of `crd` binary.
+    fn crd() -> CustomResourceDefinition {
+        let yaml = serde_yaml::to_string(&Ephemeron::crd()).unwrap();
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn test_crd_conditions_list_type() {
+        let crd = crd();
+        let schema = crd.spec.versions[0]
+            .schema
+            .as_ref()
+            .and_then(|s| s.open_api_v3_schema.as_ref())
+            .unwrap();
+        let conditions = &schema.properties.as_ref().unwrap()["status"]
+            .properties
+            .as_ref()
+            .unwrap()["conditions"];
+        assert_eq!(conditions.x_kubernetes_list_type.as_deref(), Some("map"));
+        assert_eq!(
+            conditions.x_kubernetes_list_map_keys,
+            Some(vec!["type".to_owned()])
+        );
+    }
+
+    #[test]
+    fn test_crd_printer_columns() {
+        let crd = crd();
+        let columns: Vec<_> = crd.spec.versions[0]
+            .additional_printer_columns
+            .iter()
+            .flatten()
+            .map(|c| (c.name.as_str(), c.json_path.as_str()))
+            .collect();
+        assert_eq!(
+            columns,
+            [
+                ("Host", ".status.host"),
+                (
+                    "Available",
+                    r#".status.conditions[?(@.type=="Available")].status"#
+                ),
+                ("Expires", ".spec.expirationTime"),
+                ("Age", ".metadata.creationTimestamp"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_valid_host() {
+        assert!(is_valid_host("example.com"));
+        assert!(is_valid_host("c9f3a7e0b1d2e3f4a5b6.sub-domain.example.com"));
+        assert!(is_valid_host(&format!("{}.example.com", "x".repeat(63))));
+        assert!(!is_valid_host(&format!("{}.example.com", "x".repeat(64))));
+        assert!(!is_valid_host(&format!("{}.com", "x.".repeat(126))));
+        assert!(!is_valid_host(""));
+        assert!(!is_valid_host("example..com"));
+        assert!(!is_valid_host("example.com."));
+        assert!(!is_valid_host("-example.com"));
+        assert!(!is_valid_host("example-.com"));
+        assert!(!is_valid_host("Example.com"));
+        assert!(!is_valid_host("ex_ample.com"));
+    }
+
+    #[test]
+    fn test_app_namespace() {
+        assert_eq!(
+            app_namespace("example").as_deref(),
+            Some("ephemeron-example")
+        );
+        assert_eq!(app_namespace("my-app").as_deref(), Some("ephemeron-my-app"));
+        // Valid app names that would be invalid namespace names.
+        assert_eq!(app_namespace("my.app"), None);
+        assert_eq!(app_namespace("MyApp"), None);
+        assert_eq!(app_namespace("my_app"), None);
+        assert_eq!(app_namespace("app-"), None);
+        assert_eq!(app_namespace(&"x".repeat(60)), None);
+    }
+
+    #[test]
+    fn test_namespace_or() {
+        let mut eph = test_ephemeron(serde_json::json!({"image": "nginx", "port": 80}));
+        assert_eq!(eph.namespace_or("default"), "default");
+        eph.metadata.annotations = Some(BTreeMap::from([(
+            NAMESPACE_ANNOTATION.to_owned(),
+            "ephemeron-example".to_owned(),
+        )]));
+        assert_eq!(eph.namespace_or("default"), "ephemeron-example");
+    }
+
+    #[test]
+    fn test_is_http_routable() {
+        let protocol = |protocol: Option<&str>| {
+            test_ephemeron(serde_json::json!({"image": "nginx", "port": 80, "protocol": protocol}))
+        };
+        assert!(protocol(None).is_http_routable());
+        assert!(protocol(Some("TCP")).is_http_routable());
+        assert!(!protocol(Some("UDP")).is_http_routable());
+        assert!(!protocol(Some("SCTP")).is_http_routable());
+    }
+
+    #[test]
+    fn test_creator_label() {
+        let label = creator_label("user.example");
+        assert!(is_label_value(&label));
+        assert_eq!(label, creator_label("user.example"));
+        assert_ne!(label, creator_label("user.other"));
+    }
+
+    #[test]
+    fn test_phase_from_conditions() {
+        let phase = |cs: &[EphemeronCondition]| EphemeronPhase::from_conditions(cs);
+        assert_eq!(phase(&[]), EphemeronPhase::Pending);
+        let creating = EphemeronCondition::pod_ready(Some(false), "PodCreating", None);
+        assert_eq!(phase(&[creating]), EphemeronPhase::Pending);
+        let unknown = EphemeronCondition::pod_ready(None, "PodUnknown", None);
+        assert_eq!(phase(&[unknown]), EphemeronPhase::Unknown);
+        let waiting = EphemeronCondition::pod_ready(Some(false), "ContainerCreating", None);
+        assert_eq!(
+            phase(std::slice::from_ref(&waiting)),
+            EphemeronPhase::Starting
+        );
+
+        let available = EphemeronCondition::available(Some(true), "EndpointsReady", None);
+        let expiring = EphemeronCondition::expiring(Some(true), "ExpiringSoon", None);
+        let failed = EphemeronCondition::failed(Some(true), "OOMKilled", None);
+        assert_eq!(
+            phase(&[waiting.clone(), available.clone()]),
+            EphemeronPhase::Available
+        );
+        assert_eq!(
+            phase(&[waiting.clone(), available.clone(), expiring.clone()]),
+            EphemeronPhase::Expiring
+        );
+        assert_eq!(
+            phase(&[waiting, available, expiring, failed]),
+            EphemeronPhase::Failed
+        );
+    }
+
+    #[test]
+    fn test_conditions_phase() {
+        let mut eph = test_ephemeron(serde_json::json!({"image": "nginx", "port": 80}));
+        assert_eq!(eph.conditions_phase(), EphemeronPhase::Pending);
+        // Uses the conditions as written, not the recorded phase.
+        eph.status = Some(EphemeronStatus {
+            conditions: vec![
+                EphemeronCondition::pod_ready(Some(false), "PodReplacing", None),
+                EphemeronCondition::available(Some(false), "PodReplacing", None),
+            ],
+            phase: Some(EphemeronPhase::Available),
+            ..EphemeronStatus::default()
+        });
+        assert_eq!(eph.conditions_phase(), EphemeronPhase::Pending);
+    }
+}
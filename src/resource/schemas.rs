@@ -11,6 +11,42 @@ pub fn port(_: &mut SchemaGenerator) -> Schema {
     .unwrap()
 }
 
+pub fn arch(_: &mut SchemaGenerator) -> Schema {
+    from_value(json!({
+        "type": "string",
+        "nullable": true,
+        "enum": ["amd64", "arm64"],
+    }))
+    .unwrap()
+}
+
+pub fn service_type(_: &mut SchemaGenerator) -> Schema {
+    from_value(json!({
+        "type": "string",
+        "nullable": true,
+        "enum": ["ClusterIP", "NodePort", "LoadBalancer"],
+    }))
+    .unwrap()
+}
+
+pub fn protocol(_: &mut SchemaGenerator) -> Schema {
+    from_value(json!({
+        "type": "string",
+        "nullable": true,
+        "enum": ["TCP", "UDP", "SCTP"],
+    }))
+    .unwrap()
+}
+
+pub fn probe_scheme(_: &mut SchemaGenerator) -> Schema {
+    from_value(json!({
+        "type": "string",
+        "nullable": true,
+        "enum": ["HTTP", "HTTPS"],
+    }))
+    .unwrap()
+}
+
 pub fn observed_generation(_: &mut SchemaGenerator) -> Schema {
     from_value(json!({
         "type": "integer",
@@ -36,6 +72,18 @@ pub fn conditions(_: &mut SchemaGenerator) -> Schema {
                     "format": "date-time",
                     "type": "string"
                 },
+                "reason": {
+                    "description": "Machine-readable reason for the condition's last transition in `CamelCase`.",
+                    "maxLength": 1024,
+                    "minLength": 1,
+                    "pattern": "^[A-Za-z]([A-Za-z0-9_,:]*[A-Za-z0-9_])?$",
+                    "type": "string"
+                },
+                "message": {
+                    "description": "Human-readable message indicating details about the transition.",
+                    "maxLength": 32768,
+                    "type": "string"
+                },
                 "status": {
                     "default": "Unknown",
                     "description": "Status of the condition.",